@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
 
-use crate::state::Config;
+use crate::error::ErrorCode;
+use crate::state::{
+    Config, OrderBook, RiskLimits, TriggerOrder, TwapOrder, VaultDepositor, WithdrawalRequest,
+};
+use crate::PositionDirection;
 use anchor_spl::token::{Mint, Token, TokenAccount};
 use clearing_house::program::ClearingHouse;
 use clearing_house::state::history::funding_rate::FundingRateHistory;
@@ -16,24 +20,37 @@ use clearing_house::state::{
 #[instruction(
     config_nonce: u8,
     collateral_vault_nonce: u8,
+    sub_account_id: u16,
+    risk_limits_nonce: u8,
 )]
 pub struct Initialize<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
     #[account(
         init,
-        seeds = [b"drift_client".as_ref()],
+        seeds = [
+            b"drift_client".as_ref(),
+            admin.key().as_ref(),
+            sub_account_id.to_le_bytes().as_ref()
+        ],
         bump = config_nonce,
         payer = admin
     )]
     pub config: Box<Account<'info, Config>>,
     #[account(
-        constraint = &clearing_house_state.collateral_mint.eq(&collateral_mint.key())
+        init,
+        seeds = [b"risk_limits".as_ref(), config.key().as_ref()],
+        bump = risk_limits_nonce,
+        payer = admin
+    )]
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        constraint = &clearing_house_state.collateral_mint.eq(&collateral_mint.key()) @ ErrorCode::InvalidCollateralMint
     )]
     pub collateral_mint: Box<Account<'info, Mint>>,
     #[account(
         init,
-        seeds = [b"collateral_vault".as_ref()],
+        seeds = [b"collateral_vault".as_ref(), config.key().as_ref()],
         bump = collateral_vault_nonce,
         payer = admin,
         token::mint = collateral_mint,
@@ -70,6 +87,88 @@ pub struct InitializeUser<'info> {
     pub clearing_house_program: Program<'info, ClearingHouse>,
 }
 
+/// Clearing house accounts open_position and close_position are forwarded with, checked against
+/// the clearing house state. Contexts embedding it check the user accounts against their config
+#[derive(Accounts)]
+pub struct ClearingHouseTrade<'info> {
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub state: Box<Account<'info, State>>,
+    #[account(mut)]
+    pub user: Box<Account<'info, User>>,
+    #[account(mut)]
+    pub user_positions: AccountLoader<'info, UserPositions>,
+    #[account(
+        mut,
+        constraint = &state.markets.eq(&markets.key()) @ ErrorCode::InvalidClearingHouseMarkets
+    )]
+    pub markets: AccountLoader<'info, Markets>,
+    #[account(
+        mut,
+        constraint = &state.funding_payment_history.eq(&funding_payment_history.key()) @ ErrorCode::InvalidClearingHouseFundingPaymentHistory
+    )]
+    pub funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
+    #[account(
+        mut,
+        constraint = &state.funding_rate_history.eq(&funding_rate_history.key()) @ ErrorCode::InvalidClearingHouseFundingRateHistory
+    )]
+    pub funding_rate_history: AccountLoader<'info, FundingRateHistory>,
+    #[account(
+        mut,
+        constraint = &state.trade_history.eq(&trade_history.key()) @ ErrorCode::InvalidClearingHouseTradeHistory
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+    pub program: Program<'info, ClearingHouse>,
+}
+
+/// Clearing house accounts deposit_collateral and withdraw_collateral are forwarded with, checked
+/// against the clearing house state. Contexts embedding it check the user accounts against their
+/// config
+#[derive(Accounts)]
+pub struct ClearingHouseCollateral<'info> {
+    pub authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub state: Box<Account<'info, State>>,
+    #[account(mut)]
+    pub user: Box<Account<'info, User>>,
+    #[account(mut)]
+    pub user_positions: AccountLoader<'info, UserPositions>,
+    #[account(
+        constraint = &state.markets.eq(&markets.key()) @ ErrorCode::InvalidClearingHouseMarkets
+    )]
+    pub markets: AccountLoader<'info, Markets>,
+    #[account(
+        mut,
+        constraint = &state.collateral_vault.eq(&collateral_vault.key()) @ ErrorCode::InvalidClearingHouseCollateralVault
+    )]
+    pub collateral_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &state.collateral_vault_authority.eq(&collateral_vault_authority.key()) @ ErrorCode::InvalidClearingHouseCollateralVaultAuthority
+    )]
+    pub collateral_vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = &state.insurance_vault.eq(&insurance_vault.key()) @ ErrorCode::InvalidClearingHouseInsuranceVault
+    )]
+    pub insurance_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &state.insurance_vault_authority.eq(&insurance_vault_authority.key()) @ ErrorCode::InvalidClearingHouseInsuranceVaultAuthority
+    )]
+    pub insurance_vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = &state.funding_payment_history.eq(&funding_payment_history.key()) @ ErrorCode::InvalidClearingHouseFundingPaymentHistory
+    )]
+    pub funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
+    #[account(
+        mut,
+        constraint = &state.deposit_history.eq(&deposit_history.key()) @ ErrorCode::InvalidClearingHouseDepositHistory
+    )]
+    pub deposit_history: AccountLoader<'info, DepositHistory>,
+    pub token_program: Program<'info, Token>,
+    pub program: Program<'info, ClearingHouse>,
+}
+
 #[derive(Accounts)]
 pub struct DepositCollateral<'info> {
     #[account(signer)]
@@ -77,6 +176,7 @@ pub struct DepositCollateral<'info> {
     #[account(mut)]
     pub admin_collateral_account: Box<Account<'info, TokenAccount>>,
     #[account(
+        mut,
         has_one = admin
     )]
     pub config: Box<Account<'info, Config>>,
@@ -86,24 +186,11 @@ pub struct DepositCollateral<'info> {
     )]
     pub collateral_vault: Box<Account<'info, TokenAccount>>,
     #[account(
-        constraint = &config.authority.eq(&authority.key())
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
     )]
-    pub authority: AccountInfo<'info>,
-    #[account(mut)]
-    pub clearing_house_state: Box<Account<'info, State>>,
-    #[account(mut)]
-    pub clearing_house_user: Box<Account<'info, User>>,
-    #[account(mut)]
-    pub clearing_house_collateral_vault: Box<Account<'info, TokenAccount>>,
-    pub token_program: Program<'info, Token>,
-    pub clearing_house_markets: AccountLoader<'info, Markets>,
-    #[account(mut)]
-    pub clearing_house_user_positions: AccountInfo<'info>,
-    #[account(mut)]
-    pub clearing_house_funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
-    #[account(mut)]
-    pub clearing_house_deposit_history: AccountLoader<'info, DepositHistory>,
-    pub clearing_house_program: Program<'info, ClearingHouse>,
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
 }
 
 #[derive(Accounts)]
@@ -113,7 +200,10 @@ pub struct WithdrawCollateral<'info> {
     #[account(mut)]
     pub admin_collateral_account: Box<Account<'info, TokenAccount>>,
     #[account(
-        has_one = admin
+        mut,
+        has_one = admin,
+        constraint = config.withdrawal_delay == 0 @ ErrorCode::WithdrawalDelayActive,
+        constraint = config.is_withdrawal_destination(&admin_collateral_account.key()) @ ErrorCode::InvalidWithdrawalDestination
     )]
     pub config: Box<Account<'info, Config>>,
     #[account(
@@ -122,86 +212,503 @@ pub struct WithdrawCollateral<'info> {
     )]
     pub collateral_vault: Box<Account<'info, TokenAccount>>,
     #[account(
-        constraint = &config.authority.eq(&authority.key())
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
     )]
-    pub authority: AccountInfo<'info>,
-    #[account(mut)]
-    pub clearing_house_state: Box<Account<'info, State>>,
-    #[account(mut)]
-    pub clearing_house_user: Box<Account<'info, User>>,
-    #[account(mut)]
-    pub clearing_house_collateral_vault: Box<Account<'info, TokenAccount>>,
-    pub clearing_house_collateral_vault_authority: AccountInfo<'info>,
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(withdrawal_request_nonce: u8)]
+pub struct RequestWithdrawal<'info> {
     #[account(mut)]
-    pub clearing_house_insurance_vault: Box<Account<'info, TokenAccount>>,
-    pub clearing_house_insurance_vault_authority: AccountInfo<'info>,
-    pub token_program: Program<'info, Token>,
-    pub clearing_house_markets: AccountLoader<'info, Markets>,
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [b"withdrawal_request".as_ref(), config.key().as_ref()],
+        bump = withdrawal_request_nonce,
+        payer = admin
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelWithdrawal<'info> {
     #[account(mut)]
-    pub clearing_house_user_positions: AccountInfo<'info>,
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        has_one = config,
+        close = admin
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWithdrawal<'info> {
     #[account(mut)]
-    pub clearing_house_funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
+    pub admin: Signer<'info>,
     #[account(mut)]
-    pub clearing_house_deposit_history: AccountLoader<'info, DepositHistory>,
-    pub clearing_house_program: Program<'info, ClearingHouse>,
+    pub admin_collateral_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        has_one = admin,
+        constraint = config.is_withdrawal_destination(&admin_collateral_account.key()) @ ErrorCode::InvalidWithdrawalDestination
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        has_one = config,
+        close = admin
+    )]
+    pub withdrawal_request: Box<Account<'info, WithdrawalRequest>>,
+    #[account(
+        mut,
+        constraint = &config.collateral_vault.eq(&collateral_vault.key())
+    )]
+    pub collateral_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
 }
 
 #[derive(Accounts)]
+#[instruction(direction: PositionDirection, quote_asset_amount: u128, market_index: u64)]
 pub struct OpenPosition<'info> {
     #[account(signer)]
-    pub admin: AccountInfo<'info>,
+    pub trader: AccountInfo<'info>,
+    #[account(
+        constraint = config.admin.eq(&trader.key()) || config.delegate.eq(&trader.key()),
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_index: u64)]
+pub struct ClosePosition<'info> {
+    #[account(signer)]
+    pub trader: AccountInfo<'info>,
+    #[account(
+        constraint = config.admin.eq(&trader.key()) || config.delegate.eq(&trader.key())
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDelegate<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    pub admin: Signer<'info>,
     #[account(
+        mut,
         has_one = admin
     )]
     pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    pub pending_admin: Signer<'info>,
     #[account(
-        constraint = &config.authority.eq(&authority.key())
+        mut,
+        has_one = pending_admin
     )]
-    pub authority: AccountInfo<'info>,
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateRiskLimits<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin,
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
     #[account(mut)]
-    pub clearing_house_state: Box<Account<'info, State>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracleGuards<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateWithdrawalDelay<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateWithdrawalDestinations<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+#[instruction(vault_depositor_nonce: u8)]
+pub struct InitializeVaultDepositor<'info> {
     #[account(mut)]
-    pub clearing_house_user: Box<Account<'info, User>>,
+    pub depositor: Signer<'info>,
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [b"vault_depositor".as_ref(), config.key().as_ref(), depositor.key().as_ref()],
+        bump = vault_depositor_nonce,
+        payer = depositor
+    )]
+    pub vault_depositor: Box<Account<'info, VaultDepositor>>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VaultDeposit<'info> {
+    #[account(signer)]
+    pub depositor: AccountInfo<'info>,
     #[account(mut)]
-    pub clearing_house_markets: AccountLoader<'info, Markets>,
-    pub oracle: AccountInfo<'info>,
+    pub depositor_collateral_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        has_one = config,
+        has_one = depositor
+    )]
+    pub vault_depositor: Box<Account<'info, VaultDepositor>>,
     #[account(mut)]
-    pub clearing_house_user_positions: AccountInfo<'info>,
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        constraint = &config.collateral_vault.eq(&collateral_vault.key())
+    )]
+    pub collateral_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VaultWithdraw<'info> {
+    #[account(signer)]
+    pub depositor: AccountInfo<'info>,
     #[account(mut)]
-    pub clearing_house_funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
+    pub depositor_collateral_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        has_one = config,
+        has_one = depositor
+    )]
+    pub vault_depositor: Box<Account<'info, VaultDepositor>>,
     #[account(mut)]
-    pub clearing_house_funding_rate_history: AccountLoader<'info, FundingRateHistory>,
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        constraint = &config.collateral_vault.eq(&collateral_vault.key())
+    )]
+    pub collateral_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(trigger_order_nonce: u8, market_index: u64)]
+pub struct InitializeTriggerOrder<'info> {
     #[account(mut)]
-    pub clearing_house_trade_history: AccountLoader<'info, TradeHistory>,
-    pub clearing_house_program: Program<'info, ClearingHouse>,
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [b"trigger_order".as_ref(), config.key().as_ref(), market_index.to_le_bytes().as_ref()],
+        bump = trigger_order_nonce,
+        payer = admin
+    )]
+    pub trigger_order: Box<Account<'info, TriggerOrder>>,
+    #[account(
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_user_positions: AccountLoader<'info, UserPositions>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ClosePosition<'info> {
-    #[account(signer)]
-    pub admin: AccountInfo<'info>,
+pub struct UpdateTriggerOrder<'info> {
+    pub admin: Signer<'info>,
     #[account(
-    has_one = admin
+        has_one = admin
     )]
     pub config: Box<Account<'info, Config>>,
     #[account(
-    constraint = &config.authority.eq(&authority.key())
+        mut,
+        has_one = config
     )]
-    pub authority: AccountInfo<'info>,
-    #[account(mut)]
-    pub clearing_house_state: Box<Account<'info, State>>,
-    #[account(mut)]
-    pub clearing_house_user: Box<Account<'info, User>>,
+    pub trigger_order: Box<Account<'info, TriggerOrder>>,
+    #[account(
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_user_positions: AccountLoader<'info, UserPositions>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTrigger<'info> {
+    pub keeper: Signer<'info>,
+    #[account(
+        mut,
+        constraint = &keeper_collateral_account.mint.eq(&collateral_vault.mint) @ ErrorCode::InvalidCollateralMint
+    )]
+    pub keeper_collateral_account: Box<Account<'info, TokenAccount>>,
     #[account(mut)]
-    pub clearing_house_markets: AccountLoader<'info, Markets>,
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        has_one = config
+    )]
+    pub trigger_order: Box<Account<'info, TriggerOrder>>,
+    #[account(
+        mut,
+        constraint = &config.collateral_vault.eq(&collateral_vault.key())
+    )]
+    pub collateral_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house_collateral.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house_collateral.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house_collateral.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house_collateral: ClearingHouseCollateral<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(trigger_order.market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
     pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_book_nonce: u8)]
+pub struct InitializeOrderBook<'info> {
     #[account(mut)]
-    pub clearing_house_user_positions: AccountInfo<'info>,
-    #[account(mut)]
-    pub clearing_house_funding_payment_history: AccountLoader<'info, FundingPaymentHistory>,
-    #[account(mut)]
-    pub clearing_house_funding_rate_history: AccountLoader<'info, FundingRateHistory>,
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [b"order_book".as_ref(), config.key().as_ref()],
+        bump = order_book_nonce,
+        payer = admin
+    )]
+    pub order_book: Box<Account<'info, OrderBook>>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageOrders<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        has_one = config
+    )]
+    pub order_book: Box<Account<'info, OrderBook>>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct FillOrder<'info> {
+    pub keeper: Signer<'info>,
+    #[account(
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        mut,
+        has_one = config
+    )]
+    pub order_book: Box<Account<'info, OrderBook>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(order_book.find_order(order_id)?.market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(twap_order_nonce: u8, market_index: u64)]
+pub struct InitializeTwapOrder<'info> {
     #[account(mut)]
-    pub clearing_house_trade_history: AccountLoader<'info, TradeHistory>,
-    pub clearing_house_program: Program<'info, ClearingHouse>,
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        seeds = [b"twap_order".as_ref(), config.key().as_ref(), market_index.to_le_bytes().as_ref()],
+        bump = twap_order_nonce,
+        payer = admin
+    )]
+    pub twap_order: Box<Account<'info, TwapOrder>>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageTwapOrder<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        mut,
+        has_one = config
+    )]
+    pub twap_order: Box<Account<'info, TwapOrder>>,
+}
+
+#[derive(Accounts)]
+pub struct CrankTwap<'info> {
+    pub keeper: Signer<'info>,
+    #[account(
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        mut,
+        has_one = config
+    )]
+    pub twap_order: Box<Account<'info, TwapOrder>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(twap_order.market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Rebalance<'info> {
+    #[account(signer)]
+    pub trader: AccountInfo<'info>,
+    #[account(
+        constraint = config.admin.eq(&trader.key()) || config.delegate.eq(&trader.key()),
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_index: u64)]
+pub struct SetTargetPosition<'info> {
+    #[account(signer)]
+    pub trader: AccountInfo<'info>,
+    #[account(
+        constraint = config.admin.eq(&trader.key()) || config.delegate.eq(&trader.key()),
+        has_one = risk_limits
+    )]
+    pub config: Box<Account<'info, Config>>,
+    pub risk_limits: Box<Account<'info, RiskLimits>>,
+    #[account(
+        constraint = &config.authority.eq(&clearing_house.authority.key()),
+        constraint = &config.clearing_house_user.eq(&clearing_house.user.key()) @ ErrorCode::InvalidClearingHouseUser,
+        constraint = &config.clearing_house_user_positions.eq(&clearing_house.user_positions.key()) @ ErrorCode::InvalidClearingHouseUserPositions
+    )]
+    pub clearing_house: ClearingHouseTrade<'info>,
+    #[account(
+        constraint = clearing_house.markets.load()?.get_market(market_index).amm.oracle.eq(&oracle.key()) @ ErrorCode::InvalidOracle
+    )]
+    pub oracle: AccountInfo<'info>,
 }
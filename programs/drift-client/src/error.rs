@@ -6,4 +6,122 @@ pub enum ErrorCode {
     InvalidAuthority,
     #[msg("Clearing house not collateral account authority")]
     InvalidCollateralAccountAuthority,
+    #[msg("Math Error")]
+    MathError,
+    #[msg("Vault has no equity to back outstanding shares")]
+    VaultEquityZero,
+    #[msg("Vault depositor does not have enough shares")]
+    InsufficientShares,
+    #[msg("Deposit or withdrawal amount is too small")]
+    AmountTooSmall,
+    #[msg("Market is not allowed by risk limits")]
+    MarketNotAllowed,
+    #[msg("Trade exceeds max notional for market")]
+    MaxMarketNotionalExceeded,
+    #[msg("Trade exceeds max leverage")]
+    MaxLeverageExceeded,
+    #[msg("Limit price exceeds max slippage from oracle")]
+    MaxOracleSlippageExceeded,
+    #[msg("Oracle does not match market")]
+    InvalidOracle,
+    #[msg("Oracle price is invalid")]
+    InvalidOraclePrice,
+    #[msg("Oracle is not trading")]
+    OracleNotTrading,
+    #[msg("Oracle price is stale")]
+    OracleStale,
+    #[msg("Oracle confidence is too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Mark price diverges too far from the oracle price")]
+    MarkOracleDivergenceTooLarge,
+    #[msg("No position to reduce")]
+    NoPositionToReduce,
+    #[msg("Reduce only order would increase position")]
+    ReduceOnlyIncreasesPosition,
+    #[msg("Reduce only order would flip position")]
+    ReduceOnlyFlipsPosition,
+    #[msg("Collateral mint does not match clearing house state")]
+    InvalidCollateralMint,
+    #[msg("Clearing house user does not match config")]
+    InvalidClearingHouseUser,
+    #[msg("Clearing house user positions does not match config")]
+    InvalidClearingHouseUserPositions,
+    #[msg("Clearing house collateral vault does not match clearing house state")]
+    InvalidClearingHouseCollateralVault,
+    #[msg("Clearing house collateral vault authority does not match clearing house state")]
+    InvalidClearingHouseCollateralVaultAuthority,
+    #[msg("Clearing house insurance vault does not match clearing house state")]
+    InvalidClearingHouseInsuranceVault,
+    #[msg("Clearing house insurance vault authority does not match clearing house state")]
+    InvalidClearingHouseInsuranceVaultAuthority,
+    #[msg("Clearing house markets does not match clearing house state")]
+    InvalidClearingHouseMarkets,
+    #[msg("Clearing house deposit history does not match clearing house state")]
+    InvalidClearingHouseDepositHistory,
+    #[msg("Clearing house funding payment history does not match clearing house state")]
+    InvalidClearingHouseFundingPaymentHistory,
+    #[msg("Clearing house funding rate history does not match clearing house state")]
+    InvalidClearingHouseFundingRateHistory,
+    #[msg("Clearing house trade history does not match clearing house state")]
+    InvalidClearingHouseTradeHistory,
+    #[msg("No position to close")]
+    NoPositionToClose,
+    #[msg("Trigger condition not met")]
+    TriggerConditionNotMet,
+    #[msg("Order book is full")]
+    OrderBookFull,
+    #[msg("Order not found")]
+    OrderNotFound,
+    #[msg("Order has expired")]
+    OrderExpired,
+    #[msg("Mark price has not crossed the order's limit price")]
+    LimitPriceNotCrossed,
+    #[msg("Twap must have between 1 and 16 slices")]
+    InvalidTwapSlices,
+    #[msg("Twap is still in progress")]
+    TwapInProgress,
+    #[msg("Twap has no slices left")]
+    TwapComplete,
+    #[msg("Next twap slice is not due yet")]
+    TwapSliceNotDue,
+    #[msg("Rebalance needs one oracle per leg in remaining accounts")]
+    InvalidRebalanceOracles,
+    #[msg("Withdrawals must be requested and claimed after the withdrawal delay")]
+    WithdrawalDelayActive,
+    #[msg("Withdrawal delay can only be increased")]
+    WithdrawalDelayDecreased,
+    #[msg("Withdrawal delay has not passed")]
+    WithdrawalNotClaimable,
+    #[msg("Token account is not an allowed withdrawal destination")]
+    InvalidWithdrawalDestination,
+    #[msg("No withdrawal destination is pending")]
+    NoPendingWithdrawalDestination,
+    #[msg("Withdrawal destination delay has not passed")]
+    WithdrawalDestinationNotAcceptable,
+    #[msg("Withdrawal destinations are full")]
+    WithdrawalDestinationsFull,
+    #[msg("Withdrawal destination not found")]
+    WithdrawalDestinationNotFound,
+    #[msg("Can't remove the last withdrawal destination")]
+    LastWithdrawalDestination,
+    #[msg("Oracle price exponent must not be positive")]
+    InvalidOracleExponent,
+    #[msg("Keeper fee is above the max keeper fee")]
+    KeeperFeeTooLarge,
+    #[msg("Position direction changed since the trigger was set")]
+    TriggerDirectionMismatch,
+    #[msg("Twap duration must not be negative")]
+    InvalidTwapDuration,
+    #[msg("Twap slices must each trade a non-zero amount")]
+    TwapSliceTooSmall,
+    #[msg("Withdrawal delay is above the max withdrawal delay")]
+    WithdrawalDelayTooLong,
+    #[msg("Trade toward the target position rounds to zero")]
+    TargetTradeTooSmall,
+    #[msg("Max leverage must not be 0")]
+    InvalidMaxLeverage,
+    #[msg("Max oracle slippage must be below SLIPPAGE_PRECISION")]
+    InvalidMaxOracleSlippage,
+    #[msg("Share price needs the oracle of every open position in remaining accounts")]
+    InvalidEquityOracles,
 }
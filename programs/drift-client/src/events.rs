@@ -0,0 +1,177 @@
+use crate::PositionDirection;
+use anchor_lang::prelude::*;
+use clearing_house::state::user::UserPositions;
+
+#[event]
+pub struct InitializeEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub admin: Pubkey,
+    pub owner: Pubkey,
+    pub sub_account_id: u16,
+    pub authority: Pubkey,
+    pub collateral_vault: Pubkey,
+}
+
+#[event]
+pub struct InitializeUserEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub clearing_house_user: Pubkey,
+    pub clearing_house_user_positions: Pubkey,
+}
+
+#[event]
+pub struct DepositCollateralEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    /// Shares minted, the admin shares minted for admin deposits
+    pub shares: u128,
+    pub collateral: u128,
+}
+
+#[event]
+pub struct WithdrawCollateralEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    /// Shares burned, the admin shares burned for admin withdrawals
+    pub shares: u128,
+    pub collateral: u128,
+}
+
+#[event]
+pub struct RequestWithdrawalEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub amount: u64,
+    pub claimable_ts: i64,
+}
+
+#[event]
+pub struct ProposeWithdrawalDestinationEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub destination: Pubkey,
+    pub acceptable_ts: i64,
+}
+
+#[event]
+pub struct UpdateWithdrawalDestinationsEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub withdrawal_destinations: [Pubkey; 4],
+}
+
+#[event]
+pub struct OpenPositionEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub direction: PositionDirection,
+    pub quote_asset_amount: u128,
+    pub market_index: u64,
+    pub limit_price: u128,
+    pub reduce_only: bool,
+    pub collateral: u128,
+    pub base_asset_amount_after: i128,
+    pub quote_asset_amount_after: u128,
+}
+
+#[event]
+pub struct ClosePositionEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub market_index: u64,
+    pub collateral: u128,
+    pub base_asset_amount_after: i128,
+    pub quote_asset_amount_after: u128,
+}
+
+#[event]
+pub struct ExecuteTriggerEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub market_index: u64,
+    pub keeper: Pubkey,
+    pub oracle_price: u128,
+    pub keeper_fee: u64,
+    pub collateral: u128,
+}
+
+#[event]
+pub struct PlaceOrderEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub order_id: u64,
+    pub direction: PositionDirection,
+    pub quote_asset_amount: u128,
+    pub market_index: u64,
+    pub price: u128,
+    pub expiry_ts: i64,
+}
+
+#[event]
+pub struct CancelOrderEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub order_id: u64,
+}
+
+#[event]
+pub struct FillOrderEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub order_id: u64,
+    pub keeper: Pubkey,
+    pub mark_price: u128,
+    pub collateral: u128,
+    pub base_asset_amount_after: i128,
+    pub quote_asset_amount_after: u128,
+}
+
+#[event]
+pub struct TwapFillEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub market_index: u64,
+    pub keeper: Pubkey,
+    pub slice: u64,
+    pub quote_asset_amount: u128,
+    pub base_asset_amount: i128,
+    pub collateral: u128,
+    pub base_asset_amount_after: i128,
+    pub quote_asset_amount_after: u128,
+}
+
+#[event]
+pub struct SetTargetPositionEvent {
+    pub ts: i64,
+    pub config: Pubkey,
+    pub market_index: u64,
+    pub target_base_asset_amount: i128,
+    pub base_asset_amount_before: i128,
+    /// Quote asset amount traded after any close, 0 when only closing
+    pub quote_asset_amount: u128,
+    pub collateral: u128,
+    pub base_asset_amount_after: i128,
+    pub quote_asset_amount_after: u128,
+}
+
+/// Base and quote asset amount of the position in market_index, zero if there is none
+pub fn position_snapshot(user_positions: &UserPositions, market_index: u64) -> (i128, u128) {
+    user_positions
+        .positions
+        .iter()
+        .find(|market_position| {
+            market_position.market_index == market_index && market_position.base_asset_amount != 0
+        })
+        .map_or((0, 0), |market_position| {
+            (
+                market_position.base_asset_amount,
+                market_position.quote_asset_amount,
+            )
+        })
+}
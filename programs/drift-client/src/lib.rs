@@ -1,4 +1,6 @@
-use crate::state::Config;
+use crate::state::{
+    Config, MarketRiskLimit, OracleGuards, Order, TriggerOrder, TwapFill, MAX_WITHDRAWAL_DELAY,
+};
 use anchor_lang::prelude::*;
 use anchor_spl::token::{self, Token, TokenAccount, Transfer};
 use borsh::{BorshDeserialize, BorshSerialize};
@@ -16,10 +18,17 @@ use clearing_house::cpi::accounts::{
 use clearing_house::state::state::State;
 use context::*;
 use error::ErrorCode;
+use events::*;
+use orders::TargetPositionAction;
 
 mod context;
-mod error;
-mod state;
+pub mod error;
+pub mod events;
+mod math;
+mod oracle;
+pub mod orders;
+pub mod risk;
+pub mod state;
 
 declare_id!("23gFaqsYmR1zrQVYiyWJ5xnEFqmuKLYoGasgewDTHD1u");
 
@@ -32,16 +41,22 @@ pub mod drift_client {
         ctx: Context<Initialize>,
         _config_nonce: u8,
         _collateral_vault_nonce: u8,
+        sub_account_id: u16,
+        _risk_limits_nonce: u8,
+        withdrawal_destination: Pubkey,
     ) -> ProgramResult {
+        let config_key = ctx.accounts.config.to_account_info().key;
         let collateral_account_key = ctx.accounts.collateral_vault.to_account_info().key;
 
         let (authority, authority_nonce) = Pubkey::find_program_address(
-            &[ctx
-                .accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref()],
+            &[
+                ctx.accounts
+                    .clearing_house_state
+                    .to_account_info()
+                    .owner
+                    .as_ref(),
+                config_key.as_ref(),
+            ],
             ctx.program_id,
         );
 
@@ -53,186 +68,532 @@ pub mod drift_client {
             return Err(ErrorCode::InvalidCollateralAccountAuthority.into());
         }
 
+        // the risk limits start with every market disabled, nothing can be traded until the admin
+        // sets them with update_risk_limits
+        ctx.accounts.risk_limits.config = *config_key;
+
         **ctx.accounts.config = Config {
             admin: ctx.accounts.admin.key(),
+            pending_admin: Pubkey::default(),
+            owner: ctx.accounts.admin.key(),
+            sub_account_id,
+            delegate: Pubkey::default(),
             collateral_vault: *collateral_account_key,
             authority,
             authority_nonce,
             clearing_house_user: Pubkey::default(),
             clearing_house_user_positions: Pubkey::default(),
+            total_shares: 0,
+            risk_limits: ctx.accounts.risk_limits.key(),
+            // the default pubkey leaves the list empty, blocking withdrawals until a destination
+            // is accepted
+            withdrawal_destinations: [
+                withdrawal_destination,
+                Pubkey::default(),
+                Pubkey::default(),
+                Pubkey::default(),
+            ],
+            ..Config::default()
         };
 
+        emit!(InitializeEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: *config_key,
+            admin: ctx.accounts.admin.key(),
+            owner: ctx.accounts.admin.key(),
+            sub_account_id,
+            authority,
+            collateral_vault: *collateral_account_key,
+        });
+
         Ok(())
     }
 
     pub fn initialize_user(ctx: Context<InitializeUser>, _user_nonce: u8) -> ProgramResult {
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program = ctx.accounts.clearing_house_program.to_account_info();
-        let cpi_accounts = InitializeUserWithExplicitPayer {
-            state: ctx.accounts.clearing_house_state.to_account_info(),
-            user: ctx.accounts.clearing_house_user.to_account_info(),
-            user_positions: ctx.accounts.clearing_house_user_positions.clone(),
-            authority: ctx.accounts.authority.clone(),
-            payer: ctx.accounts.admin.clone(),
-            rent: ctx.accounts.rent.to_account_info(),
-            system_program: ctx.accounts.system_program.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers);
-        clearing_house::cpi::initialize_user_with_explicit_payer(
-            cpi_ctx,
-            _user_nonce,
-            InitializeUserOptionalAccounts {
-                whitelist_token: false,
+        let clearing_house_program = ctx.accounts.clearing_house_program.to_account_info();
+        with_authority_signer(
+            &ctx.accounts.config,
+            clearing_house_program.key,
+            |signers| {
+                let cpi_accounts = InitializeUserWithExplicitPayer {
+                    state: ctx.accounts.clearing_house_state.to_account_info(),
+                    user: ctx.accounts.clearing_house_user.to_account_info(),
+                    user_positions: ctx.accounts.clearing_house_user_positions.clone(),
+                    authority: ctx.accounts.authority.clone(),
+                    payer: ctx.accounts.admin.clone(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                };
+                let cpi_ctx = CpiContext::new_with_signer(
+                    clearing_house_program.clone(),
+                    cpi_accounts,
+                    signers,
+                );
+                clearing_house::cpi::initialize_user_with_explicit_payer(
+                    cpi_ctx,
+                    _user_nonce,
+                    InitializeUserOptionalAccounts {
+                        whitelist_token: false,
+                    },
+                )
             },
         )?;
 
         let config = &mut ctx.accounts.config;
 
         config.clearing_house_user = ctx.accounts.clearing_house_user.key();
-        config.clearing_house_user = ctx.accounts.clearing_house_user_positions.key();
+        config.clearing_house_user_positions = ctx.accounts.clearing_house_user_positions.key();
+
+        emit!(InitializeUserEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: config.key(),
+            clearing_house_user: config.clearing_house_user,
+            clearing_house_user_positions: config.clearing_house_user_positions,
+        });
 
         Ok(())
     }
 
+    /// Mints admin shares at the oracle valued equity, remaining_accounts holds the oracle of every
+    /// open position
     pub fn deposit_collateral(ctx: Context<DepositCollateral>, amount: u64) -> ProgramResult {
+        let equity = ctx
+            .accounts
+            .clearing_house_collateral
+            .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+        let config = &mut ctx.accounts.config;
+        math::seed_admin_shares(config, equity);
+        let shares = math::calculate_shares_for_deposit(amount, config.total_shares, equity)?;
+        config.admin_shares = config
+            .admin_shares
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathError)?;
+        config.total_shares = config
+            .total_shares
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathError)?;
+
         // Send collateral to client collateral vault
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_accounts = Transfer {
-            from: ctx
-                .accounts
-                .admin_collateral_account
-                .to_account_info()
-                .clone(),
-            to: ctx.accounts.collateral_vault.to_account_info().clone(),
-            authority: ctx.accounts.admin.clone(),
-        };
-        let cpi_context = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers);
-        token::transfer(cpi_context, amount)?;
+        transfer_to_collateral_vault(
+            &ctx.accounts.clearing_house_collateral,
+            Transfer {
+                from: ctx.accounts.admin_collateral_account.to_account_info(),
+                to: ctx.accounts.collateral_vault.to_account_info(),
+                authority: ctx.accounts.admin.clone(),
+            },
+            amount,
+        )?;
 
         // Send collateral from client collateral vault to clearing house
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program = ctx.accounts.clearing_house_program.to_account_info();
-        let cpi_accounts = ClearingHouseDepositCollateral {
-            state: ctx.accounts.clearing_house_state.to_account_info(),
-            user: ctx.accounts.clearing_house_user.to_account_info(),
-            user_positions: ctx.accounts.clearing_house_user_positions.to_account_info(),
-            authority: ctx.accounts.authority.clone(),
-            collateral_vault: ctx
-                .accounts
-                .clearing_house_collateral_vault
-                .to_account_info(),
-            user_collateral_account: ctx.accounts.collateral_vault.to_account_info(),
-            markets: ctx.accounts.clearing_house_markets.to_account_info(),
-            deposit_history: ctx
-                .accounts
-                .clearing_house_deposit_history
-                .to_account_info(),
-            funding_payment_history: ctx
-                .accounts
-                .clearing_house_funding_payment_history
-                .to_account_info(),
-            token_program: ctx.accounts.token_program.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers);
-        clearing_house::cpi::deposit_collateral(cpi_ctx, amount)?;
+        deposit_collateral_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            amount,
+        )?;
+
+        ctx.accounts.clearing_house_collateral.user.reload()?;
+        emit!(DepositCollateralEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            depositor: ctx.accounts.admin.key(),
+            amount,
+            shares,
+            collateral: ctx.accounts.clearing_house_collateral.user.collateral,
+        });
+
         Ok(())
     }
 
+    /// Burns admin shares at the oracle valued equity, oracles as for deposit_collateral
     pub fn withdraw_collateral(ctx: Context<WithdrawCollateral>, amount: u64) -> ProgramResult {
+        let equity = ctx
+            .accounts
+            .clearing_house_collateral
+            .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+        let shares = burn_admin_shares(&mut ctx.accounts.config, amount, equity)?;
+
         // Withdraw collateral from clearing house to client vault
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program = ctx.accounts.clearing_house_program.to_account_info();
-        let cpi_accounts = ClearingHouseWithdrawCollateral {
-            state: ctx.accounts.clearing_house_state.to_account_info(),
-            user: ctx.accounts.clearing_house_user.to_account_info(),
-            user_positions: ctx.accounts.clearing_house_user_positions.to_account_info(),
-            authority: ctx.accounts.authority.clone(),
-            collateral_vault: ctx
-                .accounts
-                .clearing_house_collateral_vault
-                .to_account_info(),
-            collateral_vault_authority: ctx
-                .accounts
-                .clearing_house_collateral_vault_authority
-                .to_account_info(),
-            insurance_vault: ctx
-                .accounts
-                .clearing_house_insurance_vault
-                .to_account_info(),
-            insurance_vault_authority: ctx
-                .accounts
-                .clearing_house_insurance_vault_authority
-                .to_account_info(),
-            user_collateral_account: ctx.accounts.collateral_vault.to_account_info(),
-            markets: ctx.accounts.clearing_house_markets.to_account_info(),
-            deposit_history: ctx
-                .accounts
-                .clearing_house_deposit_history
-                .to_account_info(),
-            funding_payment_history: ctx
-                .accounts
-                .clearing_house_funding_payment_history
-                .to_account_info(),
-            token_program: ctx.accounts.token_program.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers);
-        clearing_house::cpi::withdraw_collateral(cpi_ctx, amount)?;
+        withdraw_collateral_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            amount,
+        )?;
 
         // Send collateral to client collateral vault
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.collateral_vault.to_account_info().clone(),
-            to: ctx
-                .accounts
-                .admin_collateral_account
-                .to_account_info()
-                .clone(),
-            authority: ctx.accounts.authority.clone(),
-        };
-        let cpi_context = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers);
-        token::transfer(cpi_context, amount)?;
+        transfer_from_collateral_vault(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.admin_collateral_account.to_account_info(),
+            amount,
+        )?;
+
+        ctx.accounts.clearing_house_collateral.user.reload()?;
+        emit!(WithdrawCollateralEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            depositor: ctx.accounts.admin.key(),
+            amount,
+            shares,
+            collateral: ctx.accounts.clearing_house_collateral.user.collateral,
+        });
+
+        Ok(())
+    }
+
+    pub fn request_withdrawal(
+        ctx: Context<RequestWithdrawal>,
+        _withdrawal_request_nonce: u8,
+        amount: u64,
+    ) -> ProgramResult {
+        let now = Clock::get()?.unix_timestamp;
+        let withdrawal_request = &mut ctx.accounts.withdrawal_request;
+        withdrawal_request.config = ctx.accounts.config.key();
+        withdrawal_request.amount = amount;
+        withdrawal_request.request_ts = now;
+
+        emit!(RequestWithdrawalEvent {
+            ts: now,
+            config: ctx.accounts.config.key(),
+            amount,
+            claimable_ts: now
+                .checked_add(ctx.accounts.config.withdrawal_delay)
+                .ok_or(ErrorCode::MathError)?,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_withdrawal(_ctx: Context<CancelWithdrawal>) -> ProgramResult {
+        Ok(())
+    }
+
+    /// Burns admin shares like withdraw_collateral, with the same oracles
+    pub fn claim_withdrawal(ctx: Context<ClaimWithdrawal>) -> ProgramResult {
+        let claimable_ts = ctx
+            .accounts
+            .withdrawal_request
+            .request_ts
+            .checked_add(ctx.accounts.config.withdrawal_delay)
+            .ok_or(ErrorCode::MathError)?;
+        if Clock::get()?.unix_timestamp < claimable_ts {
+            return Err(ErrorCode::WithdrawalNotClaimable.into());
+        }
+
+        let amount = ctx.accounts.withdrawal_request.amount;
+        let equity = ctx
+            .accounts
+            .clearing_house_collateral
+            .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+        let shares = burn_admin_shares(&mut ctx.accounts.config, amount, equity)?;
+
+        withdraw_collateral_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            amount,
+        )?;
+        transfer_from_collateral_vault(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.admin_collateral_account.to_account_info(),
+            amount,
+        )?;
+
+        ctx.accounts.clearing_house_collateral.user.reload()?;
+        emit!(WithdrawCollateralEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            depositor: ctx.accounts.admin.key(),
+            amount,
+            shares,
+            collateral: ctx.accounts.clearing_house_collateral.user.collateral,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_delegate(ctx: Context<UpdateDelegate>, delegate: Pubkey) -> ProgramResult {
+        ctx.accounts.config.delegate = delegate;
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, pending_admin: Pubkey) -> ProgramResult {
+        ctx.accounts.config.pending_admin = pending_admin;
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        config.admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        Ok(())
+    }
+
+    pub fn update_risk_limits(
+        ctx: Context<UpdateRiskLimits>,
+        max_leverage: u128,
+        max_oracle_slippage: u128,
+        markets: [MarketRiskLimit; 8],
+    ) -> ProgramResult {
+        if max_leverage == 0 {
+            return Err(ErrorCode::InvalidMaxLeverage.into());
+        }
+
+        // a full slippage would put the short limit price at 0, which the clearing house reads as
+        // no limit
+        if max_oracle_slippage >= risk::SLIPPAGE_PRECISION {
+            return Err(ErrorCode::InvalidMaxOracleSlippage.into());
+        }
+
+        let risk_limits = &mut ctx.accounts.risk_limits;
+        risk_limits.max_leverage = max_leverage;
+        risk_limits.max_oracle_slippage = max_oracle_slippage;
+        risk_limits.markets = markets;
+
+        Ok(())
+    }
+
+    pub fn update_oracle_guards(
+        ctx: Context<UpdateOracleGuards>,
+        oracle_guards: OracleGuards,
+    ) -> ProgramResult {
+        ctx.accounts.config.oracle_guards = oracle_guards;
+        Ok(())
+    }
+
+    pub fn update_withdrawal_delay(
+        ctx: Context<UpdateWithdrawalDelay>,
+        withdrawal_delay: i64,
+    ) -> ProgramResult {
+        // a lower delay would let a compromised admin skip the timelock
+        if withdrawal_delay < ctx.accounts.config.withdrawal_delay {
+            return Err(ErrorCode::WithdrawalDelayDecreased.into());
+        }
+
+        // a higher delay would let a compromised admin lock the deposits for good
+        if withdrawal_delay > MAX_WITHDRAWAL_DELAY {
+            return Err(ErrorCode::WithdrawalDelayTooLong.into());
+        }
+
+        ctx.accounts.config.withdrawal_delay = withdrawal_delay;
+        Ok(())
+    }
+
+    /// The destination can be accepted once the withdrawal destination delay has passed,
+    /// proposing the default pubkey cancels a pending destination
+    pub fn propose_withdrawal_destination(
+        ctx: Context<UpdateWithdrawalDestinations>,
+        destination: Pubkey,
+    ) -> ProgramResult {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        config.pending_withdrawal_destination = destination;
+        config.pending_withdrawal_destination_ts = now;
+
+        emit!(ProposeWithdrawalDestinationEvent {
+            ts: now,
+            config: config.key(),
+            destination,
+            acceptable_ts: now
+                .checked_add(config.withdrawal_destination_delay())
+                .ok_or(ErrorCode::MathError)?,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_withdrawal_destination(
+        ctx: Context<UpdateWithdrawalDestinations>,
+    ) -> ProgramResult {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        let destination = config.pending_withdrawal_destination;
+        if destination == Pubkey::default() {
+            return Err(ErrorCode::NoPendingWithdrawalDestination.into());
+        }
+
+        let acceptable_ts = config
+            .pending_withdrawal_destination_ts
+            .checked_add(config.withdrawal_destination_delay())
+            .ok_or(ErrorCode::MathError)?;
+        if now < acceptable_ts {
+            return Err(ErrorCode::WithdrawalDestinationNotAcceptable.into());
+        }
+
+        if !config.withdrawal_destinations.contains(&destination) {
+            let slot = config
+                .withdrawal_destinations
+                .iter_mut()
+                .find(|withdrawal_destination| **withdrawal_destination == Pubkey::default())
+                .ok_or(ErrorCode::WithdrawalDestinationsFull)?;
+            *slot = destination;
+        }
+        config.pending_withdrawal_destination = Pubkey::default();
+        config.pending_withdrawal_destination_ts = 0;
+
+        emit!(UpdateWithdrawalDestinationsEvent {
+            ts: now,
+            config: config.key(),
+            withdrawal_destinations: config.withdrawal_destinations,
+        });
+
+        Ok(())
+    }
+
+    /// Takes effect immediately, but can't empty the list since an empty list blocks withdrawals
+    /// until a new destination is accepted
+    pub fn remove_withdrawal_destination(
+        ctx: Context<UpdateWithdrawalDestinations>,
+        destination: Pubkey,
+    ) -> ProgramResult {
+        let config = &mut ctx.accounts.config;
+        let destinations = config
+            .withdrawal_destinations
+            .iter()
+            .filter(|withdrawal_destination| **withdrawal_destination != Pubkey::default())
+            .count();
+        let slot = config
+            .withdrawal_destinations
+            .iter_mut()
+            .find(|withdrawal_destination| {
+                destination != Pubkey::default() && **withdrawal_destination == destination
+            })
+            .ok_or(ErrorCode::WithdrawalDestinationNotFound)?;
+        if destinations == 1 {
+            return Err(ErrorCode::LastWithdrawalDestination.into());
+        }
+        *slot = Pubkey::default();
+
+        emit!(UpdateWithdrawalDestinationsEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: config.key(),
+            withdrawal_destinations: config.withdrawal_destinations,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_vault_depositor(
+        ctx: Context<InitializeVaultDepositor>,
+        _vault_depositor_nonce: u8,
+    ) -> ProgramResult {
+        let vault_depositor = &mut ctx.accounts.vault_depositor;
+        vault_depositor.config = ctx.accounts.config.key();
+        vault_depositor.depositor = ctx.accounts.depositor.key();
+        vault_depositor.shares = 0;
+
+        Ok(())
+    }
+
+    /// Mints depositor shares at the oracle valued equity, oracles as for deposit_collateral
+    pub fn vault_deposit(ctx: Context<VaultDeposit>, amount: u64) -> ProgramResult {
+        let equity = ctx
+            .accounts
+            .clearing_house_collateral
+            .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+        math::seed_admin_shares(&mut ctx.accounts.config, equity);
+        let shares =
+            math::calculate_shares_for_deposit(amount, ctx.accounts.config.total_shares, equity)?;
+
+        // Send collateral to client collateral vault
+        transfer_to_collateral_vault(
+            &ctx.accounts.clearing_house_collateral,
+            Transfer {
+                from: ctx.accounts.depositor_collateral_account.to_account_info(),
+                to: ctx.accounts.collateral_vault.to_account_info(),
+                authority: ctx.accounts.depositor.clone(),
+            },
+            amount,
+        )?;
+
+        // Send collateral from client collateral vault to clearing house
+        deposit_collateral_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            amount,
+        )?;
+
+        let vault_depositor = &mut ctx.accounts.vault_depositor;
+        vault_depositor.shares = vault_depositor
+            .shares
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathError)?;
+
+        let config = &mut ctx.accounts.config;
+        config.total_shares = config
+            .total_shares
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathError)?;
+
+        ctx.accounts.clearing_house_collateral.user.reload()?;
+        emit!(DepositCollateralEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            shares,
+            collateral: ctx.accounts.clearing_house_collateral.user.collateral,
+        });
+
+        Ok(())
+    }
+
+    /// Pays out shares at the oracle valued equity, oracles as for deposit_collateral
+    pub fn vault_withdraw(ctx: Context<VaultWithdraw>, shares: u128) -> ProgramResult {
+        if shares > ctx.accounts.vault_depositor.shares {
+            return Err(ErrorCode::InsufficientShares.into());
+        }
+
+        let equity = ctx
+            .accounts
+            .clearing_house_collateral
+            .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+        let amount =
+            math::calculate_amount_for_shares(shares, ctx.accounts.config.total_shares, equity)?;
+
+        // Withdraw collateral from clearing house to client vault
+        withdraw_collateral_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            amount,
+        )?;
+
+        // Send collateral from client collateral vault to depositor
+        transfer_from_collateral_vault(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house_collateral,
+            ctx.accounts.collateral_vault.to_account_info(),
+            ctx.accounts.depositor_collateral_account.to_account_info(),
+            amount,
+        )?;
+
+        let vault_depositor = &mut ctx.accounts.vault_depositor;
+        vault_depositor.shares = vault_depositor
+            .shares
+            .checked_sub(shares)
+            .ok_or(ErrorCode::MathError)?;
+
+        let config = &mut ctx.accounts.config;
+        config.total_shares = config
+            .total_shares
+            .checked_sub(shares)
+            .ok_or(ErrorCode::MathError)?;
+
+        ctx.accounts.clearing_house_collateral.user.reload()?;
+        emit!(WithdrawCollateralEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            shares,
+            collateral: ctx.accounts.clearing_house_collateral.user.collateral,
+        });
 
         Ok(())
     }
@@ -245,36 +606,768 @@ pub mod drift_client {
         limit_price: u128,
         optional_accounts: ManagePositionOptionalAccounts,
     ) -> ProgramResult {
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program: AccountInfo<'info> = ctx.accounts.clearing_house_program.to_account_info();
-        let cpi_accounts = ClearingHouseOpenPosition {
-            state: ctx.accounts.clearing_house_state.to_account_info(),
-            user: ctx.accounts.clearing_house_user.to_account_info(),
-            user_positions: ctx.accounts.clearing_house_user_positions.to_account_info(),
-            authority: ctx.accounts.authority.clone(),
-            markets: ctx.accounts.clearing_house_markets.to_account_info(),
-            oracle: ctx.accounts.oracle.clone(),
-            trade_history: ctx.accounts.clearing_house_trade_history.to_account_info(),
-            funding_payment_history: ctx
-                .accounts
-                .clearing_house_funding_payment_history
-                .to_account_info(),
-            funding_rate_history: ctx
+        let limit_price = {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            let oracle_price = oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            risk::validate_open_position(
+                &ctx.accounts.risk_limits,
+                &ctx.accounts.clearing_house.user,
+                &user_positions,
+                &markets,
+                oracle_price,
+                direction,
+                quote_asset_amount,
+                market_index,
+                limit_price,
+            )?
+        };
+
+        open_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            ctx.remaining_accounts,
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            optional_accounts,
+        )?;
+
+        emit_open_position_event(
+            ctx.accounts,
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            false,
+        )
+    }
+
+    pub fn reduce_position<'info>(
+        ctx: Context<'_, '_, '_, 'info, OpenPosition<'info>>,
+        direction: PositionDirection,
+        quote_asset_amount: u128,
+        market_index: u64,
+        limit_price: u128,
+        optional_accounts: ManagePositionOptionalAccounts,
+    ) -> ProgramResult {
+        {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            risk::validate_reduce_position(
+                &user_positions,
+                &markets,
+                direction,
+                quote_asset_amount,
+                market_index,
+            )?;
+        }
+
+        open_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            ctx.remaining_accounts,
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            optional_accounts,
+        )?;
+
+        emit_open_position_event(
+            ctx.accounts,
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            true,
+        )
+    }
+
+    pub fn close_position<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClosePosition<'info>>,
+        market_index: u64,
+        optional_accounts: ManagePositionOptionalAccounts,
+    ) -> ProgramResult {
+        {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+        }
+
+        close_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            ctx.remaining_accounts,
+            market_index,
+            optional_accounts,
+        )?;
+
+        ctx.accounts.clearing_house.user.reload()?;
+        let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+            &*ctx.accounts.clearing_house.user_positions.load()?,
+            market_index,
+        );
+        emit!(ClosePositionEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            market_index,
+            collateral: ctx.accounts.clearing_house.user.collateral,
+            base_asset_amount_after,
+            quote_asset_amount_after,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_trigger_order(
+        ctx: Context<InitializeTriggerOrder>,
+        _trigger_order_nonce: u8,
+        market_index: u64,
+        stop_loss_price: u128,
+        take_profit_price: u128,
+        keeper_fee: u64,
+    ) -> ProgramResult {
+        let trigger_order = &mut ctx.accounts.trigger_order;
+        trigger_order.config = ctx.accounts.config.key();
+        trigger_order.market_index = market_index;
+        trigger_order.direction = orders::trigger_direction(
+            &*ctx.accounts.clearing_house_user_positions.load()?,
+            market_index,
+        )?;
+        trigger_order.stop_loss_price = stop_loss_price;
+        trigger_order.take_profit_price = take_profit_price;
+        trigger_order.set_keeper_fee(keeper_fee, Clock::get()?.unix_timestamp)?;
+        Ok(())
+    }
+
+    pub fn update_trigger_order(
+        ctx: Context<UpdateTriggerOrder>,
+        stop_loss_price: u128,
+        take_profit_price: u128,
+        keeper_fee: u64,
+    ) -> ProgramResult {
+        let trigger_order = &mut ctx.accounts.trigger_order;
+        trigger_order.direction = orders::trigger_direction(
+            &*ctx.accounts.clearing_house_user_positions.load()?,
+            trigger_order.market_index,
+        )?;
+        trigger_order.stop_loss_price = stop_loss_price;
+        trigger_order.take_profit_price = take_profit_price;
+        trigger_order.set_keeper_fee(keeper_fee, Clock::get()?.unix_timestamp)?;
+        Ok(())
+    }
+
+    /// Permissionless: closes the position once the oracle crosses the stop loss or take profit,
+    /// pays the keeper fee and disarms the trigger. The fee is the admin's cost, it's burned from
+    /// the admin shares and capped at what they back, so the depositors' share price is unchanged.
+    /// remaining_accounts holds the oracle of every position still open after the close
+    pub fn execute_trigger<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteTrigger<'info>>,
+    ) -> ProgramResult {
+        let market_index = ctx.accounts.trigger_order.market_index;
+        let oracle_price = {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            let oracle_price = oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            let direction = orders::trigger_direction(&user_positions, market_index)?;
+            if direction != ctx.accounts.trigger_order.direction {
+                return Err(ErrorCode::TriggerDirectionMismatch.into());
+            }
+
+            if !orders::trigger_condition_met(&ctx.accounts.trigger_order, oracle_price) {
+                return Err(ErrorCode::TriggerConditionNotMet.into());
+            }
+            oracle_price
+        };
+
+        close_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            &[],
+            market_index,
+            ManagePositionOptionalAccounts::default(),
+        )?;
+
+        let keeper_fee = ctx.accounts.trigger_order.update_keeper_fee(
+            ctx.accounts.config.withdrawal_delay,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let keeper_fee = if keeper_fee > 0 {
+            ctx.accounts.clearing_house_collateral.user.reload()?;
+            let equity = ctx
                 .accounts
-                .clearing_house_funding_rate_history
-                .to_account_info(),
+                .clearing_house_collateral
+                .equity(ctx.remaining_accounts, &ctx.accounts.config.oracle_guards)?;
+            let config = &mut ctx.accounts.config;
+            math::seed_admin_shares(config, equity);
+            let admin_equity = math::calculate_admin_equity(config, equity)?;
+            let keeper_fee = (keeper_fee as u128).min(admin_equity) as u64;
+            if keeper_fee > 0 {
+                burn_admin_shares(config, keeper_fee, equity)?;
+            }
+            keeper_fee
+        } else {
+            0
         };
-        let remaining_accounts = ctx.remaining_accounts;
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers)
-            .with_remaining_accounts(remaining_accounts.into());
+        if keeper_fee > 0 {
+            withdraw_collateral_cpi(
+                &ctx.accounts.config,
+                &ctx.accounts.clearing_house_collateral,
+                ctx.accounts.collateral_vault.to_account_info(),
+                keeper_fee,
+            )?;
+            transfer_from_collateral_vault(
+                &ctx.accounts.config,
+                &ctx.accounts.clearing_house_collateral,
+                ctx.accounts.collateral_vault.to_account_info(),
+                ctx.accounts.keeper_collateral_account.to_account_info(),
+                keeper_fee,
+            )?;
+        }
+
+        let trigger_order = &mut ctx.accounts.trigger_order;
+        trigger_order.stop_loss_price = 0;
+        trigger_order.take_profit_price = 0;
+
+        ctx.accounts.clearing_house.user.reload()?;
+        emit!(ExecuteTriggerEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            market_index,
+            keeper: ctx.accounts.keeper.key(),
+            oracle_price,
+            keeper_fee,
+            collateral: ctx.accounts.clearing_house.user.collateral,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_order_book(
+        ctx: Context<InitializeOrderBook>,
+        _order_book_nonce: u8,
+    ) -> ProgramResult {
+        ctx.accounts.order_book.config = ctx.accounts.config.key();
+        ctx.accounts.order_book.next_order_id = 1;
+        Ok(())
+    }
+
+    pub fn place_order(
+        ctx: Context<ManageOrders>,
+        direction: PositionDirection,
+        quote_asset_amount: u128,
+        market_index: u64,
+        price: u128,
+        expiry_ts: i64,
+    ) -> ProgramResult {
+        let order_id = ctx.accounts.order_book.place_order(Order {
+            id: 0,
+            direction,
+            quote_asset_amount,
+            market_index,
+            price,
+            expiry_ts,
+        })?;
+
+        emit!(PlaceOrderEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            order_id,
+            direction,
+            quote_asset_amount,
+            market_index,
+            price,
+            expiry_ts,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_order(ctx: Context<ManageOrders>, order_id: u64) -> ProgramResult {
+        ctx.accounts.order_book.remove_order(order_id)?;
+
+        emit!(CancelOrderEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            order_id,
+        });
+
+        Ok(())
+    }
+
+    /// Permissionless: opens the order's position once the mark price crosses its limit price
+    pub fn fill_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, FillOrder<'info>>,
+        order_id: u64,
+    ) -> ProgramResult {
+        let order = *ctx.accounts.order_book.find_order(order_id)?;
+        if orders::order_expired(&order, Clock::get()?.unix_timestamp) {
+            return Err(ErrorCode::OrderExpired.into());
+        }
+
+        let (mark_price, limit_price) = {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            let amm = &markets.get_market(order.market_index).amm;
+            let mark_price = amm.mark_price()?;
+            if !orders::limit_price_crossed(&order, mark_price) {
+                return Err(ErrorCode::LimitPriceNotCrossed.into());
+            }
+
+            let oracle_price = oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            let limit_price = risk::validate_open_position(
+                &ctx.accounts.risk_limits,
+                &ctx.accounts.clearing_house.user,
+                &user_positions,
+                &markets,
+                oracle_price,
+                order.direction,
+                order.quote_asset_amount,
+                order.market_index,
+                order.price,
+            )?;
+            (mark_price, limit_price)
+        };
+
+        open_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            &[],
+            order.direction,
+            order.quote_asset_amount,
+            order.market_index,
+            limit_price,
+            ManagePositionOptionalAccounts::default(),
+        )?;
+
+        ctx.accounts.order_book.remove_order(order_id)?;
+
+        ctx.accounts.clearing_house.user.reload()?;
+        let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+            &*ctx.accounts.clearing_house.user_positions.load()?,
+            order.market_index,
+        );
+        emit!(FillOrderEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            order_id,
+            keeper: ctx.accounts.keeper.key(),
+            mark_price,
+            collateral: ctx.accounts.clearing_house.user.collateral,
+            base_asset_amount_after,
+            quote_asset_amount_after,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_twap_order(
+        ctx: Context<InitializeTwapOrder>,
+        _twap_order_nonce: u8,
+        market_index: u64,
+    ) -> ProgramResult {
+        ctx.accounts.twap_order.config = ctx.accounts.config.key();
+        ctx.accounts.twap_order.market_index = market_index;
+        Ok(())
+    }
+
+    /// Schedules num_slices equal slices of total_quote_asset_amount over duration seconds,
+    /// the first one executable right away
+    pub fn start_twap(
+        ctx: Context<ManageTwapOrder>,
+        direction: PositionDirection,
+        total_quote_asset_amount: u128,
+        num_slices: u64,
+        duration: i64,
+    ) -> ProgramResult {
+        if duration < 0 {
+            return Err(ErrorCode::InvalidTwapDuration.into());
+        }
+
+        let twap_order = &mut ctx.accounts.twap_order;
+        if num_slices == 0 || num_slices as usize > twap_order.fills.len() {
+            return Err(ErrorCode::InvalidTwapSlices.into());
+        }
+
+        if total_quote_asset_amount / num_slices as u128 == 0 {
+            return Err(ErrorCode::TwapSliceTooSmall.into());
+        }
+
+        if !twap_order.is_complete() {
+            return Err(ErrorCode::TwapInProgress.into());
+        }
+
+        twap_order.direction = direction;
+        twap_order.total_quote_asset_amount = total_quote_asset_amount;
+        twap_order.num_slices = num_slices;
+        twap_order.slice_interval = duration / num_slices as i64;
+        twap_order.start_ts = Clock::get()?.unix_timestamp;
+        twap_order.slices_filled = 0;
+        twap_order.filled_quote_asset_amount = 0;
+        twap_order.fills = [TwapFill::default(); 16];
+        Ok(())
+    }
+
+    /// Stops a twap, keeping the slices already filled
+    pub fn cancel_twap(ctx: Context<ManageTwapOrder>) -> ProgramResult {
+        let twap_order = &mut ctx.accounts.twap_order;
+        twap_order.num_slices = twap_order.slices_filled;
+        Ok(())
+    }
+
+    /// Permissionless: executes the next twap slice once its interval has elapsed
+    pub fn crank_twap<'info>(ctx: Context<'_, '_, '_, 'info, CrankTwap<'info>>) -> ProgramResult {
+        let now = Clock::get()?.unix_timestamp;
+        let twap_order = &ctx.accounts.twap_order;
+        let direction = twap_order.direction;
+        let market_index = twap_order.market_index;
+        let quote_asset_amount = twap_order.next_slice(now)?;
+
+        let (limit_price, base_asset_amount_before) = {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            let oracle_price = oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            let limit_price = risk::validate_open_position(
+                &ctx.accounts.risk_limits,
+                &ctx.accounts.clearing_house.user,
+                &user_positions,
+                &markets,
+                oracle_price,
+                direction,
+                quote_asset_amount,
+                market_index,
+                0,
+            )?;
+            let (base_asset_amount_before, _) = position_snapshot(&user_positions, market_index);
+            (limit_price, base_asset_amount_before)
+        };
+
+        open_position_cpi(
+            &ctx.accounts.config,
+            &ctx.accounts.clearing_house,
+            ctx.accounts.oracle.clone(),
+            &[],
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            ManagePositionOptionalAccounts::default(),
+        )?;
+
+        ctx.accounts.clearing_house.user.reload()?;
+        let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+            &*ctx.accounts.clearing_house.user_positions.load()?,
+            market_index,
+        );
+        let base_asset_amount = base_asset_amount_after
+            .checked_sub(base_asset_amount_before)
+            .ok_or(ErrorCode::MathError)?;
+
+        let twap_order = &mut ctx.accounts.twap_order;
+        let slice = twap_order.slices_filled;
+        twap_order.record_fill(TwapFill {
+            ts: now,
+            quote_asset_amount,
+            base_asset_amount,
+        })?;
+
+        emit!(TwapFillEvent {
+            ts: now,
+            config: ctx.accounts.config.key(),
+            market_index,
+            keeper: ctx.accounts.keeper.key(),
+            slice,
+            quote_asset_amount,
+            base_asset_amount,
+            collateral: ctx.accounts.clearing_house.user.collateral,
+            base_asset_amount_after,
+            quote_asset_amount_after,
+        });
+
+        Ok(())
+    }
+
+    /// Opens every leg in one transaction, so either all legs apply or none do. remaining_accounts
+    /// holds the oracle of each leg's market, in the order of the legs
+    pub fn rebalance<'info>(
+        ctx: Context<'_, '_, '_, 'info, Rebalance<'info>>,
+        legs: Vec<RebalanceLeg>,
+    ) -> ProgramResult {
+        if ctx.remaining_accounts.len() != legs.len() {
+            return Err(ErrorCode::InvalidRebalanceOracles.into());
+        }
+
+        for (leg, oracle) in legs.iter().zip(ctx.remaining_accounts.iter()) {
+            let limit_price = {
+                let markets = ctx.accounts.clearing_house.markets.load()?;
+                let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+                let amm = &markets.get_market(leg.market_index).amm;
+                if !amm.oracle.eq(oracle.key) {
+                    return Err(ErrorCode::InvalidOracle.into());
+                }
+
+                let oracle_price = oracle::get_oracle_price(
+                    oracle,
+                    amm,
+                    &ctx.accounts.config.oracle_guards,
+                    Clock::get()?.slot,
+                )?;
+                risk::validate_open_position(
+                    &ctx.accounts.risk_limits,
+                    &ctx.accounts.clearing_house.user,
+                    &user_positions,
+                    &markets,
+                    oracle_price,
+                    leg.direction,
+                    leg.quote_asset_amount,
+                    leg.market_index,
+                    0,
+                )?
+            };
+
+            open_position_cpi(
+                &ctx.accounts.config,
+                &ctx.accounts.clearing_house,
+                oracle.clone(),
+                &[],
+                leg.direction,
+                leg.quote_asset_amount,
+                leg.market_index,
+                limit_price,
+                ManagePositionOptionalAccounts::default(),
+            )?;
+
+            ctx.accounts.clearing_house.user.reload()?;
+            let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+                &*ctx.accounts.clearing_house.user_positions.load()?,
+                leg.market_index,
+            );
+            emit!(OpenPositionEvent {
+                ts: Clock::get()?.unix_timestamp,
+                config: ctx.accounts.config.key(),
+                direction: leg.direction,
+                quote_asset_amount: leg.quote_asset_amount,
+                market_index: leg.market_index,
+                limit_price,
+                reduce_only: false,
+                collateral: ctx.accounts.clearing_house.user.collateral,
+                base_asset_amount_after,
+                quote_asset_amount_after,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Trades the position in market_index toward target_base_asset_amount, opening, reducing,
+    /// closing or flipping it as needed. max_slippage bounds the trade price around the mark
+    /// price, SLIPPAGE_PRECISION. A position already on target is left as is without an event,
+    /// a trade that rounds to zero quote is rejected
+    pub fn set_target_position<'info>(
+        ctx: Context<'_, '_, '_, 'info, SetTargetPosition<'info>>,
+        market_index: u64,
+        target_base_asset_amount: i128,
+        max_slippage: u128,
+    ) -> ProgramResult {
+        let (base_asset_amount_before, oracle_price) = {
+            let markets = ctx.accounts.clearing_house.markets.load()?;
+            let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+            let oracle_price = oracle::get_oracle_price(
+                &ctx.accounts.oracle,
+                &markets.get_market(market_index).amm,
+                &ctx.accounts.config.oracle_guards,
+                Clock::get()?.slot,
+            )?;
+            let (base_asset_amount, _) = position_snapshot(&user_positions, market_index);
+            (base_asset_amount, oracle_price)
+        };
+
+        let action =
+            orders::target_position_action(base_asset_amount_before, target_base_asset_amount);
+        if action == TargetPositionAction::Noop {
+            return Ok(());
+        }
+
+        if action == TargetPositionAction::Close || action == TargetPositionAction::Flip {
+            close_position_cpi(
+                &ctx.accounts.config,
+                &ctx.accounts.clearing_house,
+                ctx.accounts.oracle.clone(),
+                &[],
+                market_index,
+                ManagePositionOptionalAccounts::default(),
+            )?;
+            ctx.accounts.clearing_house.user.reload()?;
+        }
+
+        let mut quote_asset_amount = 0;
+        if action != TargetPositionAction::Close {
+            let base_asset_amount = if action == TargetPositionAction::Flip {
+                0
+            } else {
+                base_asset_amount_before
+            };
+            let trade_base_asset_amount = target_base_asset_amount
+                .checked_sub(base_asset_amount)
+                .ok_or(ErrorCode::MathError)?;
+            let direction = if trade_base_asset_amount > 0 {
+                PositionDirection::Long
+            } else {
+                PositionDirection::Short
+            };
+
+            let limit_price = {
+                let markets = ctx.accounts.clearing_house.markets.load()?;
+                let user_positions = ctx.accounts.clearing_house.user_positions.load()?;
+                let mark_price = markets.get_market(market_index).amm.mark_price()?;
+                quote_asset_amount = orders::base_to_quote_asset_amount(
+                    trade_base_asset_amount.unsigned_abs(),
+                    mark_price,
+                )?;
+                if quote_asset_amount == 0 {
+                    return Err(ErrorCode::TargetTradeTooSmall.into());
+                }
+
+                let limit_price =
+                    orders::slippage_limit_price(mark_price, direction, max_slippage)?;
+                if action == TargetPositionAction::Reduce {
+                    // the mark price ignores the amm's slippage, so a large reduction can be
+                    // priced above what the position is worth and read as a flip, it's capped to
+                    // closing the position instead
+                    quote_asset_amount = quote_asset_amount.min(risk::position_base_asset_value(
+                        &user_positions,
+                        &markets,
+                        market_index,
+                    )?);
+                    risk::validate_reduce_position(
+                        &user_positions,
+                        &markets,
+                        direction,
+                        quote_asset_amount,
+                        market_index,
+                    )?;
+                    limit_price
+                } else {
+                    risk::validate_open_position(
+                        &ctx.accounts.risk_limits,
+                        &ctx.accounts.clearing_house.user,
+                        &user_positions,
+                        &markets,
+                        oracle_price,
+                        direction,
+                        quote_asset_amount,
+                        market_index,
+                        limit_price,
+                    )?
+                }
+            };
+
+            open_position_cpi(
+                &ctx.accounts.config,
+                &ctx.accounts.clearing_house,
+                ctx.accounts.oracle.clone(),
+                &[],
+                direction,
+                quote_asset_amount,
+                market_index,
+                limit_price,
+                ManagePositionOptionalAccounts::default(),
+            )?;
+        }
+
+        ctx.accounts.clearing_house.user.reload()?;
+        let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+            &*ctx.accounts.clearing_house.user_positions.load()?,
+            market_index,
+        );
+        emit!(SetTargetPositionEvent {
+            ts: Clock::get()?.unix_timestamp,
+            config: ctx.accounts.config.key(),
+            market_index,
+            target_base_asset_amount,
+            base_asset_amount_before,
+            quote_asset_amount,
+            collateral: ctx.accounts.clearing_house.user.collateral,
+            base_asset_amount_after,
+            quote_asset_amount_after,
+        });
+
+        Ok(())
+    }
+}
+
+/// Runs f with the signer seeds of the config's clearing house authority, every cpi the
+/// authority signs goes through here
+fn with_authority_signer<'info, R>(
+    config: &Account<'info, Config>,
+    clearing_house_program_id: &Pubkey,
+    f: impl FnOnce(&[&[&[u8]]]) -> R,
+) -> R {
+    let config_key = config.key();
+    let signature_seeds = [
+        clearing_house_program_id.as_ref(),
+        config_key.as_ref(),
+        bytemuck::bytes_of(&config.authority_nonce),
+    ];
+    f(&[&signature_seeds[..]])
+}
+
+#[allow(clippy::too_many_arguments)]
+fn open_position_cpi<'info>(
+    config: &Account<'info, Config>,
+    clearing_house: &ClearingHouseTrade<'info>,
+    oracle: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    direction: PositionDirection,
+    quote_asset_amount: u128,
+    market_index: u64,
+    limit_price: u128,
+    optional_accounts: ManagePositionOptionalAccounts,
+) -> ProgramResult {
+    with_authority_signer(config, clearing_house.program.key, |signers| {
+        let cpi_ctx = CpiContext::new_with_signer(
+            clearing_house.program.to_account_info(),
+            clearing_house.open_position(oracle),
+            signers,
+        )
+        .with_remaining_accounts(remaining_accounts.into());
         clearing_house::cpi::open_position(
             cpi_ctx,
             match direction {
@@ -288,45 +1381,25 @@ pub mod drift_client {
                 discount_token: optional_accounts.discount_token,
                 referrer: optional_accounts.referrer,
             },
-        )?;
-        Ok(())
-    }
+        )
+    })
+}
 
-    pub fn close_position<'info>(
-        ctx: Context<'_, '_, '_, 'info, ClosePosition<'info>>,
-        market_index: u64,
-        optional_accounts: ManagePositionOptionalAccounts,
-    ) -> ProgramResult {
-        let signature_seeds = [
-            ctx.accounts
-                .clearing_house_state
-                .to_account_info()
-                .owner
-                .as_ref(),
-            bytemuck::bytes_of(&ctx.accounts.config.authority_nonce),
-        ];
-        let signers = &[&signature_seeds[..]];
-        let cpi_program: AccountInfo<'info> = ctx.accounts.clearing_house_program.to_account_info();
-        let cpi_accounts = ClearingHouseClosePosition {
-            state: ctx.accounts.clearing_house_state.to_account_info(),
-            user: ctx.accounts.clearing_house_user.to_account_info(),
-            user_positions: ctx.accounts.clearing_house_user_positions.to_account_info(),
-            authority: ctx.accounts.authority.clone(),
-            markets: ctx.accounts.clearing_house_markets.to_account_info(),
-            oracle: ctx.accounts.oracle.clone(),
-            trade_history: ctx.accounts.clearing_house_trade_history.to_account_info(),
-            funding_payment_history: ctx
-                .accounts
-                .clearing_house_funding_payment_history
-                .to_account_info(),
-            funding_rate_history: ctx
-                .accounts
-                .clearing_house_funding_rate_history
-                .to_account_info(),
-        };
-        let remaining_accounts = ctx.remaining_accounts;
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signers)
-            .with_remaining_accounts(remaining_accounts.into());
+fn close_position_cpi<'info>(
+    config: &Account<'info, Config>,
+    clearing_house: &ClearingHouseTrade<'info>,
+    oracle: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    market_index: u64,
+    optional_accounts: ManagePositionOptionalAccounts,
+) -> ProgramResult {
+    with_authority_signer(config, clearing_house.program.key, |signers| {
+        let cpi_ctx = CpiContext::new_with_signer(
+            clearing_house.program.to_account_info(),
+            clearing_house.close_position(oracle),
+            signers,
+        )
+        .with_remaining_accounts(remaining_accounts.into());
         clearing_house::cpi::close_position(
             cpi_ctx,
             market_index,
@@ -334,11 +1407,224 @@ pub mod drift_client {
                 discount_token: optional_accounts.discount_token,
                 referrer: optional_accounts.referrer,
             },
-        )?;
-        Ok(())
+        )
+    })
+}
+
+fn deposit_collateral_cpi<'info>(
+    config: &Account<'info, Config>,
+    clearing_house: &ClearingHouseCollateral<'info>,
+    collateral_vault: AccountInfo<'info>,
+    amount: u64,
+) -> ProgramResult {
+    with_authority_signer(config, clearing_house.program.key, |signers| {
+        let cpi_ctx = CpiContext::new_with_signer(
+            clearing_house.program.to_account_info(),
+            clearing_house.deposit_collateral(collateral_vault),
+            signers,
+        );
+        clearing_house::cpi::deposit_collateral(cpi_ctx, amount)
+    })
+}
+
+fn withdraw_collateral_cpi<'info>(
+    config: &Account<'info, Config>,
+    clearing_house: &ClearingHouseCollateral<'info>,
+    collateral_vault: AccountInfo<'info>,
+    amount: u64,
+) -> ProgramResult {
+    with_authority_signer(config, clearing_house.program.key, |signers| {
+        let cpi_ctx = CpiContext::new_with_signer(
+            clearing_house.program.to_account_info(),
+            clearing_house.withdraw_collateral(collateral_vault),
+            signers,
+        );
+        clearing_house::cpi::withdraw_collateral(cpi_ctx, amount)
+    })
+}
+
+/// The transfer is signed by the owner of cpi_accounts.from, not the authority
+fn transfer_to_collateral_vault<'info>(
+    clearing_house: &ClearingHouseCollateral<'info>,
+    cpi_accounts: Transfer<'info>,
+    amount: u64,
+) -> ProgramResult {
+    let cpi_ctx = CpiContext::new(clearing_house.token_program.to_account_info(), cpi_accounts);
+    token::transfer(cpi_ctx, amount)
+}
+
+fn transfer_from_collateral_vault<'info>(
+    config: &Account<'info, Config>,
+    clearing_house: &ClearingHouseCollateral<'info>,
+    collateral_vault: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    amount: u64,
+) -> ProgramResult {
+    with_authority_signer(config, clearing_house.program.key, |signers| {
+        let cpi_ctx = CpiContext::new_with_signer(
+            clearing_house.token_program.to_account_info(),
+            Transfer {
+                from: collateral_vault,
+                to: destination,
+                authority: clearing_house.authority.clone(),
+            },
+            signers,
+        );
+        token::transfer(cpi_ctx, amount)
+    })
+}
+
+impl<'info> ClearingHouseTrade<'info> {
+    fn open_position(&self, oracle: AccountInfo<'info>) -> ClearingHouseOpenPosition<'info> {
+        ClearingHouseOpenPosition {
+            state: self.state.to_account_info(),
+            user: self.user.to_account_info(),
+            user_positions: self.user_positions.to_account_info(),
+            authority: self.authority.clone(),
+            markets: self.markets.to_account_info(),
+            oracle,
+            trade_history: self.trade_history.to_account_info(),
+            funding_payment_history: self.funding_payment_history.to_account_info(),
+            funding_rate_history: self.funding_rate_history.to_account_info(),
+        }
+    }
+
+    fn close_position(&self, oracle: AccountInfo<'info>) -> ClearingHouseClosePosition<'info> {
+        ClearingHouseClosePosition {
+            state: self.state.to_account_info(),
+            user: self.user.to_account_info(),
+            user_positions: self.user_positions.to_account_info(),
+            authority: self.authority.clone(),
+            markets: self.markets.to_account_info(),
+            oracle,
+            trade_history: self.trade_history.to_account_info(),
+            funding_payment_history: self.funding_payment_history.to_account_info(),
+            funding_rate_history: self.funding_rate_history.to_account_info(),
+        }
+    }
+}
+
+/// Burns the admin shares backing a withdrawal of amount, the admin can only withdraw equity the
+/// depositors' shares don't back
+fn burn_admin_shares(
+    config: &mut Config,
+    amount: u64,
+    equity: u128,
+) -> std::result::Result<u128, ProgramError> {
+    math::seed_admin_shares(config, equity);
+    let shares = math::calculate_shares_for_withdrawal(amount, config.total_shares, equity)?;
+    config.admin_shares = config
+        .admin_shares
+        .checked_sub(shares)
+        .ok_or(ErrorCode::InsufficientShares)?;
+    config.total_shares = config
+        .total_shares
+        .checked_sub(shares)
+        .ok_or(ErrorCode::MathError)?;
+    Ok(shares)
+}
+
+impl<'info> ClearingHouseCollateral<'info> {
+    /// Collateral plus unrealized pnl of the clearing house user at the guarded oracle prices,
+    /// what the vault's shares are worth. oracles holds the oracle of every open position, in
+    /// user_positions order
+    fn equity(
+        &self,
+        oracles: &[AccountInfo],
+        oracle_guards: &OracleGuards,
+    ) -> std::result::Result<u128, ProgramError> {
+        let user_positions = self.user_positions.load()?;
+        let markets = self.markets.load()?;
+        let open_positions = user_positions
+            .positions
+            .iter()
+            .filter(|market_position| market_position.base_asset_amount != 0);
+        if open_positions.clone().count() != oracles.len() {
+            return Err(ErrorCode::InvalidEquityOracles.into());
+        }
+
+        let slot = Clock::get()?.slot;
+        let oracle_prices = open_positions
+            .zip(oracles.iter())
+            .map(|(market_position, oracle)| {
+                let amm = &markets.get_market(market_position.market_index).amm;
+                if !amm.oracle.eq(oracle.key) {
+                    return Err(ErrorCode::InvalidOracle.into());
+                }
+                oracle::get_oracle_price(oracle, amm, oracle_guards, slot)
+            })
+            .collect::<std::result::Result<Vec<u128>, ProgramError>>()?;
+        math::calculate_oracle_equity(&self.user, &user_positions, &oracle_prices)
+    }
+
+    fn deposit_collateral(
+        &self,
+        user_collateral_account: AccountInfo<'info>,
+    ) -> ClearingHouseDepositCollateral<'info> {
+        ClearingHouseDepositCollateral {
+            state: self.state.to_account_info(),
+            user: self.user.to_account_info(),
+            user_positions: self.user_positions.to_account_info(),
+            authority: self.authority.clone(),
+            collateral_vault: self.collateral_vault.to_account_info(),
+            user_collateral_account,
+            markets: self.markets.to_account_info(),
+            deposit_history: self.deposit_history.to_account_info(),
+            funding_payment_history: self.funding_payment_history.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
+    }
+
+    fn withdraw_collateral(
+        &self,
+        user_collateral_account: AccountInfo<'info>,
+    ) -> ClearingHouseWithdrawCollateral<'info> {
+        ClearingHouseWithdrawCollateral {
+            state: self.state.to_account_info(),
+            user: self.user.to_account_info(),
+            user_positions: self.user_positions.to_account_info(),
+            authority: self.authority.clone(),
+            collateral_vault: self.collateral_vault.to_account_info(),
+            collateral_vault_authority: self.collateral_vault_authority.clone(),
+            insurance_vault: self.insurance_vault.to_account_info(),
+            insurance_vault_authority: self.insurance_vault_authority.clone(),
+            user_collateral_account,
+            markets: self.markets.to_account_info(),
+            deposit_history: self.deposit_history.to_account_info(),
+            funding_payment_history: self.funding_payment_history.to_account_info(),
+            token_program: self.token_program.to_account_info(),
+        }
     }
 }
 
+fn emit_open_position_event(
+    accounts: &mut OpenPosition,
+    direction: PositionDirection,
+    quote_asset_amount: u128,
+    market_index: u64,
+    limit_price: u128,
+    reduce_only: bool,
+) -> ProgramResult {
+    accounts.clearing_house.user.reload()?;
+    let (base_asset_amount_after, quote_asset_amount_after) = position_snapshot(
+        &*accounts.clearing_house.user_positions.load()?,
+        market_index,
+    );
+    emit!(OpenPositionEvent {
+        ts: Clock::get()?.unix_timestamp,
+        config: accounts.config.key(),
+        direction,
+        quote_asset_amount,
+        market_index,
+        limit_price,
+        reduce_only,
+        collateral: accounts.clearing_house.user.collateral,
+        base_asset_amount_after,
+        quote_asset_amount_after,
+    });
+    Ok(())
+}
+
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq)]
 pub enum PositionDirection {
     Long,
@@ -357,3 +1643,10 @@ pub struct ManagePositionOptionalAccounts {
     pub discount_token: bool,
     pub referrer: bool,
 }
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RebalanceLeg {
+    pub market_index: u64,
+    pub direction: PositionDirection,
+    pub quote_asset_amount: u128,
+}
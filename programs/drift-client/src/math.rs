@@ -0,0 +1,196 @@
+use crate::error::ErrorCode;
+use crate::orders::base_to_quote_asset_amount;
+use crate::state::Config;
+use anchor_lang::prelude::*;
+use clearing_house::math::position::calculate_base_asset_value_and_pnl;
+use clearing_house::state::market::Markets;
+use clearing_house::state::user::{User, UserPositions};
+use std::convert::TryFrom;
+
+/// Collateral plus unrealized pnl of every open position at the mark price, in QUOTE_PRECISION.
+/// The mark price can be pushed within a transaction, so shares are priced with
+/// calculate_oracle_equity instead
+pub fn calculate_equity(
+    user: &User,
+    user_positions: &UserPositions,
+    markets: &Markets,
+) -> std::result::Result<u128, ProgramError> {
+    let mut unrealized_pnl: i128 = 0;
+    for market_position in user_positions.positions.iter() {
+        if market_position.base_asset_amount == 0 {
+            continue;
+        }
+
+        let amm = &markets.get_market(market_position.market_index).amm;
+        let (_, position_unrealized_pnl) =
+            calculate_base_asset_value_and_pnl(market_position, amm)?;
+        unrealized_pnl = unrealized_pnl
+            .checked_add(position_unrealized_pnl)
+            .ok_or(ErrorCode::MathError)?;
+    }
+
+    add_unrealized_pnl(user.collateral, unrealized_pnl)
+}
+
+/// Collateral plus unrealized pnl of every open position at its oracle price, in QUOTE_PRECISION.
+/// oracle_prices holds one price per open position, in user_positions order. Otherwise a trade
+/// could push the mark price, mint shares cheap, unwind and burn them rich
+pub fn calculate_oracle_equity(
+    user: &User,
+    user_positions: &UserPositions,
+    oracle_prices: &[u128],
+) -> std::result::Result<u128, ProgramError> {
+    let mut unrealized_pnl: i128 = 0;
+    let open_positions = user_positions
+        .positions
+        .iter()
+        .filter(|market_position| market_position.base_asset_amount != 0);
+    for (market_position, oracle_price) in open_positions.zip(oracle_prices.iter()) {
+        let base_asset_value = base_to_quote_asset_amount(
+            market_position.base_asset_amount.unsigned_abs(),
+            *oracle_price,
+        )?;
+        let position_unrealized_pnl = if market_position.base_asset_amount > 0 {
+            cast_to_i128(base_asset_value)?
+                .checked_sub(cast_to_i128(market_position.quote_asset_amount)?)
+                .ok_or(ErrorCode::MathError)?
+        } else {
+            cast_to_i128(market_position.quote_asset_amount)?
+                .checked_sub(cast_to_i128(base_asset_value)?)
+                .ok_or(ErrorCode::MathError)?
+        };
+        unrealized_pnl = unrealized_pnl
+            .checked_add(position_unrealized_pnl)
+            .ok_or(ErrorCode::MathError)?;
+    }
+
+    add_unrealized_pnl(user.collateral, unrealized_pnl)
+}
+
+fn add_unrealized_pnl(
+    collateral: u128,
+    unrealized_pnl: i128,
+) -> std::result::Result<u128, ProgramError> {
+    let equity = if unrealized_pnl > 0 {
+        collateral
+            .checked_add(unrealized_pnl.unsigned_abs())
+            .ok_or(ErrorCode::MathError)?
+    } else {
+        collateral.saturating_sub(unrealized_pnl.unsigned_abs())
+    };
+
+    Ok(equity)
+}
+
+fn cast_to_i128(amount: u128) -> std::result::Result<i128, ProgramError> {
+    i128::try_from(amount).map_err(|_| ErrorCode::MathError.into())
+}
+
+/// Equity no shares back, e.g. pnl left once every depositor withdrew, belongs to the admin. It's
+/// minted as admin shares so the next deposit is priced against it instead of claiming it
+pub fn seed_admin_shares(config: &mut Config, equity: u128) {
+    if config.total_shares == 0 && equity > 0 {
+        config.admin_shares = equity;
+        config.total_shares = equity;
+    }
+}
+
+/// Equity the admin shares back, rounded down
+pub fn calculate_admin_equity(
+    config: &Config,
+    equity: u128,
+) -> std::result::Result<u128, ProgramError> {
+    if config.total_shares == 0 {
+        return Ok(0);
+    }
+
+    let admin_equity = config
+        .admin_shares
+        .checked_mul(equity)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(config.total_shares)
+        .ok_or(ErrorCode::MathError)?;
+
+    Ok(admin_equity)
+}
+
+pub fn calculate_shares_for_deposit(
+    amount: u64,
+    total_shares: u128,
+    equity: u128,
+) -> std::result::Result<u128, ProgramError> {
+    if amount == 0 {
+        return Err(ErrorCode::AmountTooSmall.into());
+    }
+
+    if total_shares == 0 {
+        return Ok(amount as u128);
+    }
+
+    if equity == 0 {
+        return Err(ErrorCode::VaultEquityZero.into());
+    }
+
+    let shares = (amount as u128)
+        .checked_mul(total_shares)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(equity)
+        .ok_or(ErrorCode::MathError)?;
+
+    if shares == 0 {
+        return Err(ErrorCode::AmountTooSmall.into());
+    }
+
+    Ok(shares)
+}
+
+/// Shares burned to withdraw amount, rounded up so the withdrawal never takes more equity than the
+/// shares back
+pub fn calculate_shares_for_withdrawal(
+    amount: u64,
+    total_shares: u128,
+    equity: u128,
+) -> std::result::Result<u128, ProgramError> {
+    if amount == 0 {
+        return Err(ErrorCode::AmountTooSmall.into());
+    }
+
+    if equity == 0 {
+        return Err(ErrorCode::VaultEquityZero.into());
+    }
+
+    let shares = (amount as u128)
+        .checked_mul(total_shares)
+        .and_then(|shares| shares.checked_add(equity - 1))
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(equity)
+        .ok_or(ErrorCode::MathError)?;
+
+    Ok(shares)
+}
+
+pub fn calculate_amount_for_shares(
+    shares: u128,
+    total_shares: u128,
+    equity: u128,
+) -> std::result::Result<u64, ProgramError> {
+    if shares > total_shares {
+        return Err(ErrorCode::InsufficientShares.into());
+    }
+
+    let amount = shares
+        .checked_mul(equity)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(total_shares)
+        .ok_or(ErrorCode::MathError)?;
+
+    if amount == 0 {
+        return Err(ErrorCode::AmountTooSmall.into());
+    }
+
+    if amount > u64::MAX as u128 {
+        return Err(ErrorCode::MathError.into());
+    }
+
+    Ok(amount as u64)
+}
@@ -0,0 +1,83 @@
+use crate::error::ErrorCode;
+use crate::state::OracleGuards;
+use anchor_lang::prelude::*;
+use clearing_house::math::constants::MARK_PRICE_PRECISION;
+use clearing_house::state::market::AMM;
+use pyth::pc::{Price, PriceStatus};
+
+pub const ORACLE_GUARD_PRECISION: u128 = 10_000;
+
+/// Oracle aggregate price, MARK_PRICE_PRECISION, after checking it against the oracle guards
+pub fn get_oracle_price(
+    oracle: &AccountInfo,
+    amm: &AMM,
+    oracle_guards: &OracleGuards,
+    slot: u64,
+) -> std::result::Result<u128, ProgramError> {
+    let price_data = Price::load_ref(oracle)?;
+    if price_data.agg.price <= 0 {
+        return Err(ErrorCode::InvalidOraclePrice.into());
+    }
+
+    if price_data.agg.status != PriceStatus::Trading {
+        return Err(ErrorCode::OracleNotTrading.into());
+    }
+
+    if oracle_guards.max_staleness_slots > 0
+        && slot.saturating_sub(price_data.agg.pub_slot) > oracle_guards.max_staleness_slots
+    {
+        return Err(ErrorCode::OracleStale.into());
+    }
+
+    if oracle_guards.max_conf_ratio > 0 {
+        let conf_ratio = (price_data.agg.conf as u128)
+            .checked_mul(ORACLE_GUARD_PRECISION)
+            .ok_or(ErrorCode::MathError)?
+            .checked_div(price_data.agg.price as u128)
+            .ok_or(ErrorCode::MathError)?;
+        if conf_ratio > oracle_guards.max_conf_ratio {
+            return Err(ErrorCode::OracleConfidenceTooWide.into());
+        }
+    }
+
+    let oracle_price = to_mark_price_precision(price_data.agg.price as u128, price_data.expo)?;
+
+    if oracle_guards.max_mark_oracle_divergence > 0 {
+        let mark_price = amm.mark_price()?;
+        let divergence = if mark_price > oracle_price {
+            mark_price - oracle_price
+        } else {
+            oracle_price - mark_price
+        }
+        .checked_mul(ORACLE_GUARD_PRECISION)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(oracle_price)
+        .ok_or(ErrorCode::MathError)?;
+        if divergence > oracle_guards.max_mark_oracle_divergence {
+            return Err(ErrorCode::MarkOracleDivergenceTooLarge.into());
+        }
+    }
+
+    Ok(oracle_price)
+}
+
+fn to_mark_price_precision(
+    oracle_price: u128,
+    expo: i32,
+) -> std::result::Result<u128, ProgramError> {
+    // pyth prices carry a non-positive exponent
+    if expo > 0 {
+        return Err(ErrorCode::InvalidOracleExponent.into());
+    }
+
+    let oracle_precision = 10_u128
+        .checked_pow(expo.unsigned_abs())
+        .ok_or(ErrorCode::MathError)?;
+    let price = oracle_price
+        .checked_mul(MARK_PRICE_PRECISION)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(oracle_precision)
+        .ok_or(ErrorCode::MathError)?;
+
+    Ok(price)
+}
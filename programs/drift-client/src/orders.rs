@@ -0,0 +1,241 @@
+use crate::error::ErrorCode;
+use crate::events::position_snapshot;
+use crate::risk::SLIPPAGE_PRECISION;
+use crate::state::{Order, OrderBook, TriggerOrder, TwapFill, TwapOrder};
+use crate::PositionDirection;
+use anchor_lang::prelude::*;
+use clearing_house::math::constants::{AMM_TO_QUOTE_PRECISION_RATIO, MARK_PRICE_PRECISION};
+use clearing_house::state::user::UserPositions;
+
+/// 1 usdc, QUOTE_PRECISION
+pub const MAX_KEEPER_FEE: u64 = 1_000_000;
+
+/// Whether the oracle price has crossed the stop loss or take profit of the position
+pub fn trigger_condition_met(trigger_order: &TriggerOrder, oracle_price: u128) -> bool {
+    let stop_loss = trigger_order.stop_loss_price != 0;
+    let take_profit = trigger_order.take_profit_price != 0;
+    if trigger_order.direction == PositionDirection::Long {
+        (stop_loss && oracle_price <= trigger_order.stop_loss_price)
+            || (take_profit && oracle_price >= trigger_order.take_profit_price)
+    } else {
+        (stop_loss && oracle_price >= trigger_order.stop_loss_price)
+            || (take_profit && oracle_price <= trigger_order.take_profit_price)
+    }
+}
+
+/// Direction of the position in market_index, which the trigger is set against
+pub fn trigger_direction(
+    user_positions: &UserPositions,
+    market_index: u64,
+) -> std::result::Result<PositionDirection, ProgramError> {
+    let (base_asset_amount, _) = position_snapshot(user_positions, market_index);
+    if base_asset_amount > 0 {
+        Ok(PositionDirection::Long)
+    } else if base_asset_amount < 0 {
+        Ok(PositionDirection::Short)
+    } else {
+        Err(ErrorCode::NoPositionToClose.into())
+    }
+}
+
+impl TriggerOrder {
+    /// Lowering the keeper fee takes effect at once, raising it waits out the withdrawal delay so
+    /// depositors can leave before it is paid
+    pub fn set_keeper_fee(&mut self, keeper_fee: u64, now: i64) -> ProgramResult {
+        if keeper_fee > MAX_KEEPER_FEE {
+            return Err(ErrorCode::KeeperFeeTooLarge.into());
+        }
+
+        if keeper_fee <= self.keeper_fee {
+            self.keeper_fee = keeper_fee;
+            self.pending_keeper_fee = 0;
+            self.pending_keeper_fee_ts = 0;
+        } else {
+            self.pending_keeper_fee = keeper_fee;
+            self.pending_keeper_fee_ts = now;
+        }
+        Ok(())
+    }
+
+    /// Applies the pending keeper fee once the withdrawal delay has passed and returns the fee
+    /// to pay
+    pub fn update_keeper_fee(
+        &mut self,
+        withdrawal_delay: i64,
+        now: i64,
+    ) -> std::result::Result<u64, ProgramError> {
+        if self.pending_keeper_fee != 0 {
+            let applicable_ts = self
+                .pending_keeper_fee_ts
+                .checked_add(withdrawal_delay)
+                .ok_or(ErrorCode::MathError)?;
+            if now >= applicable_ts {
+                self.keeper_fee = self.pending_keeper_fee;
+                self.pending_keeper_fee = 0;
+                self.pending_keeper_fee_ts = 0;
+            }
+        }
+
+        if self.keeper_fee > MAX_KEEPER_FEE {
+            return Err(ErrorCode::KeeperFeeTooLarge.into());
+        }
+        Ok(self.keeper_fee)
+    }
+}
+
+impl OrderBook {
+    pub fn place_order(&mut self, order: Order) -> std::result::Result<u64, ProgramError> {
+        let slot = self
+            .orders
+            .iter_mut()
+            .find(|slot| slot.id == 0)
+            .ok_or(ErrorCode::OrderBookFull)?;
+
+        let id = self.next_order_id;
+        *slot = Order { id, ..order };
+        self.next_order_id = self
+            .next_order_id
+            .checked_add(1)
+            .ok_or(ErrorCode::MathError)?;
+
+        Ok(id)
+    }
+
+    pub fn find_order(&self, order_id: u64) -> std::result::Result<&Order, ProgramError> {
+        self.orders
+            .iter()
+            .find(|order| order_id != 0 && order.id == order_id)
+            .ok_or_else(|| ErrorCode::OrderNotFound.into())
+    }
+
+    pub fn remove_order(&mut self, order_id: u64) -> std::result::Result<Order, ProgramError> {
+        let slot = self
+            .orders
+            .iter_mut()
+            .find(|slot| order_id != 0 && slot.id == order_id)
+            .ok_or(ErrorCode::OrderNotFound)?;
+        let order = *slot;
+        *slot = Order::default();
+        Ok(order)
+    }
+}
+
+/// Whether the mark price is at or better than the order's limit price
+pub fn limit_price_crossed(order: &Order, mark_price: u128) -> bool {
+    match order.direction {
+        PositionDirection::Long => mark_price <= order.price,
+        PositionDirection::Short => mark_price >= order.price,
+    }
+}
+
+pub fn order_expired(order: &Order, now: i64) -> bool {
+    order.expiry_ts != 0 && now > order.expiry_ts
+}
+
+impl TwapOrder {
+    pub fn is_complete(&self) -> bool {
+        self.slices_filled >= self.num_slices
+    }
+
+    /// Quote asset amount of the next slice, the last slice takes the remainder
+    pub fn next_slice(&self, now: i64) -> std::result::Result<u128, ProgramError> {
+        if self.is_complete() {
+            return Err(ErrorCode::TwapComplete.into());
+        }
+
+        let due_ts = (self.slices_filled as i64)
+            .checked_mul(self.slice_interval)
+            .and_then(|elapsed| elapsed.checked_add(self.start_ts))
+            .ok_or(ErrorCode::MathError)?;
+        if now < due_ts {
+            return Err(ErrorCode::TwapSliceNotDue.into());
+        }
+
+        if self.slices_filled + 1 == self.num_slices {
+            Ok(self
+                .total_quote_asset_amount
+                .checked_sub(self.filled_quote_asset_amount)
+                .ok_or(ErrorCode::MathError)?)
+        } else {
+            Ok(self.total_quote_asset_amount / self.num_slices as u128)
+        }
+    }
+
+    pub fn record_fill(&mut self, fill: TwapFill) -> ProgramResult {
+        self.fills[self.slices_filled as usize] = fill;
+        self.slices_filled += 1;
+        self.filled_quote_asset_amount = self
+            .filled_quote_asset_amount
+            .checked_add(fill.quote_asset_amount)
+            .ok_or(ErrorCode::MathError)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetPositionAction {
+    Noop,
+    /// Trade in the direction of the existing position, or open one
+    Open,
+    /// Trade against the existing position without crossing zero
+    Reduce,
+    Close,
+    /// Close the existing position then open one on the other side
+    Flip,
+}
+
+pub fn target_position_action(
+    base_asset_amount: i128,
+    target_base_asset_amount: i128,
+) -> TargetPositionAction {
+    let same_side = (base_asset_amount > 0) == (target_base_asset_amount > 0);
+    if base_asset_amount == target_base_asset_amount {
+        TargetPositionAction::Noop
+    } else if target_base_asset_amount == 0 {
+        TargetPositionAction::Close
+    } else if base_asset_amount == 0 {
+        TargetPositionAction::Open
+    } else if !same_side {
+        TargetPositionAction::Flip
+    } else if target_base_asset_amount.unsigned_abs() > base_asset_amount.unsigned_abs() {
+        TargetPositionAction::Open
+    } else {
+        TargetPositionAction::Reduce
+    }
+}
+
+/// Quote asset amount worth base_asset_amount at the mark price, QUOTE_PRECISION. The amm's
+/// slippage is not included, so the trade lands near the target rather than exactly on it
+pub fn base_to_quote_asset_amount(
+    base_asset_amount: u128,
+    mark_price: u128,
+) -> std::result::Result<u128, ProgramError> {
+    let quote_asset_amount = base_asset_amount
+        .checked_mul(mark_price)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(MARK_PRICE_PRECISION)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(AMM_TO_QUOTE_PRECISION_RATIO)
+        .ok_or(ErrorCode::MathError)?;
+    Ok(quote_asset_amount)
+}
+
+/// Worst price a trade accepts, max_slippage away from the mark price in SLIPPAGE_PRECISION
+pub fn slippage_limit_price(
+    mark_price: u128,
+    direction: PositionDirection,
+    max_slippage: u128,
+) -> std::result::Result<u128, ProgramError> {
+    let slippage = mark_price
+        .checked_mul(max_slippage)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(SLIPPAGE_PRECISION)
+        .ok_or(ErrorCode::MathError)?;
+    let limit_price = match direction {
+        PositionDirection::Long => mark_price
+            .checked_add(slippage)
+            .ok_or(ErrorCode::MathError)?,
+        PositionDirection::Short => mark_price.saturating_sub(slippage),
+    };
+    Ok(limit_price)
+}
@@ -0,0 +1,164 @@
+use crate::error::ErrorCode;
+use crate::math::calculate_equity;
+use crate::state::RiskLimits;
+use crate::PositionDirection;
+use anchor_lang::prelude::*;
+use clearing_house::math::position::calculate_base_asset_value_and_pnl;
+use clearing_house::state::market::Markets;
+use clearing_house::state::user::{User, UserPositions};
+
+pub const LEVERAGE_PRECISION: u128 = 10_000;
+pub const SLIPPAGE_PRECISION: u128 = 10_000;
+
+/// Checks a trade against the config's risk limits and returns the limit price to forward to the
+/// clearing house. When no limit price is given, the max oracle slippage is used as the limit.
+/// Trades that shrink the position only lower its notional, so they skip the market and leverage
+/// caps, e.g. to cut a position after its market was disabled.
+#[allow(clippy::too_many_arguments)]
+pub fn validate_open_position(
+    risk_limits: &RiskLimits,
+    user: &User,
+    user_positions: &UserPositions,
+    markets: &Markets,
+    oracle_price: u128,
+    direction: PositionDirection,
+    quote_asset_amount: u128,
+    market_index: u64,
+    limit_price: u128,
+) -> std::result::Result<u128, ProgramError> {
+    let mut other_markets_notional: u128 = 0;
+    // notional of the position in market_index after the trade
+    let mut market_notional = quote_asset_amount;
+    let mut reduces_position = false;
+    for market_position in user_positions.positions.iter() {
+        if market_position.base_asset_amount == 0 {
+            continue;
+        }
+
+        let amm = &markets.get_market(market_position.market_index).amm;
+        let (base_asset_value, _) = calculate_base_asset_value_and_pnl(market_position, amm)?;
+
+        if market_position.market_index == market_index {
+            let increases_position =
+                (market_position.base_asset_amount > 0) == (direction == PositionDirection::Long);
+            reduces_position = !increases_position && quote_asset_amount <= base_asset_value;
+            market_notional = if increases_position {
+                base_asset_value
+                    .checked_add(quote_asset_amount)
+                    .ok_or(ErrorCode::MathError)?
+            } else if base_asset_value > quote_asset_amount {
+                base_asset_value - quote_asset_amount
+            } else {
+                quote_asset_amount - base_asset_value
+            };
+        } else {
+            other_markets_notional = other_markets_notional
+                .checked_add(base_asset_value)
+                .ok_or(ErrorCode::MathError)?;
+        }
+    }
+
+    if !reduces_position {
+        let market_risk_limit = risk_limits
+            .markets
+            .iter()
+            .find(|market_risk_limit| {
+                market_risk_limit.enabled && market_risk_limit.market_index == market_index
+            })
+            .ok_or(ErrorCode::MarketNotAllowed)?;
+        if market_notional > market_risk_limit.max_notional {
+            return Err(ErrorCode::MaxMarketNotionalExceeded.into());
+        }
+
+        let equity = calculate_equity(user, user_positions, markets)?;
+        if equity == 0 {
+            return Err(ErrorCode::MaxLeverageExceeded.into());
+        }
+
+        let leverage = other_markets_notional
+            .checked_add(market_notional)
+            .ok_or(ErrorCode::MathError)?
+            .checked_mul(LEVERAGE_PRECISION)
+            .ok_or(ErrorCode::MathError)?
+            .checked_div(equity)
+            .ok_or(ErrorCode::MathError)?;
+        if leverage > risk_limits.max_leverage {
+            return Err(ErrorCode::MaxLeverageExceeded.into());
+        }
+    }
+
+    let max_slippage = oracle_price
+        .checked_mul(risk_limits.max_oracle_slippage)
+        .ok_or(ErrorCode::MathError)?
+        .checked_div(SLIPPAGE_PRECISION)
+        .ok_or(ErrorCode::MathError)?;
+    let price_bound = match direction {
+        PositionDirection::Long => oracle_price
+            .checked_add(max_slippage)
+            .ok_or(ErrorCode::MathError)?,
+        PositionDirection::Short => oracle_price.saturating_sub(max_slippage),
+    };
+
+    if limit_price == 0 {
+        return Ok(price_bound);
+    }
+
+    let exceeds_bound = match direction {
+        PositionDirection::Long => limit_price > price_bound,
+        PositionDirection::Short => limit_price < price_bound,
+    };
+    if exceeds_bound {
+        return Err(ErrorCode::MaxOracleSlippageExceeded.into());
+    }
+
+    Ok(limit_price)
+}
+
+/// Quote asset amount closing the position in market_index would return, 0 if there is none
+pub fn position_base_asset_value(
+    user_positions: &UserPositions,
+    markets: &Markets,
+    market_index: u64,
+) -> std::result::Result<u128, ProgramError> {
+    match user_positions.positions.iter().find(|market_position| {
+        market_position.market_index == market_index && market_position.base_asset_amount != 0
+    }) {
+        Some(market_position) => {
+            let amm = &markets.get_market(market_index).amm;
+            let (base_asset_value, _) = calculate_base_asset_value_and_pnl(market_position, amm)?;
+            Ok(base_asset_value)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Checks that a trade only shrinks the existing position in market_index
+pub fn validate_reduce_position(
+    user_positions: &UserPositions,
+    markets: &Markets,
+    direction: PositionDirection,
+    quote_asset_amount: u128,
+    market_index: u64,
+) -> std::result::Result<(), ProgramError> {
+    let market_position = user_positions
+        .positions
+        .iter()
+        .find(|market_position| {
+            market_position.market_index == market_index && market_position.base_asset_amount != 0
+        })
+        .ok_or(ErrorCode::NoPositionToReduce)?;
+
+    let increases_position =
+        (market_position.base_asset_amount > 0) == (direction == PositionDirection::Long);
+    if increases_position {
+        return Err(ErrorCode::ReduceOnlyIncreasesPosition.into());
+    }
+
+    let amm = &markets.get_market(market_index).amm;
+    let (base_asset_value, _) = calculate_base_asset_value_and_pnl(market_position, amm)?;
+    if quote_asset_amount > base_asset_value {
+        return Err(ErrorCode::ReduceOnlyFlipsPosition.into());
+    }
+
+    Ok(())
+}
@@ -1,12 +1,166 @@
+use crate::PositionDirection;
 use anchor_lang::prelude::*;
 
+/// 30 days, since the withdrawal delay can't be lowered once set
+pub const MAX_WITHDRAWAL_DELAY: i64 = 30 * 24 * 60 * 60;
+/// 1 day, so new withdrawal destinations are delayed even when withdrawal_delay is 0
+pub const MIN_WITHDRAWAL_DESTINATION_DELAY: i64 = 24 * 60 * 60;
+
 #[account]
 #[derive(Default)]
 pub struct Config {
     pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub owner: Pubkey,
+    pub sub_account_id: u16,
+    pub delegate: Pubkey,
     pub collateral_vault: Pubkey,
     pub authority: Pubkey,
     pub authority_nonce: u8,
     pub clearing_house_user: Pubkey,
     pub clearing_house_user_positions: Pubkey,
+    pub total_shares: u128,
+    pub risk_limits: Pubkey,
+    pub oracle_guards: OracleGuards,
+    /// Seconds between request_withdrawal and claim_withdrawal, withdraw_collateral is disabled
+    /// when not 0
+    pub withdrawal_delay: i64,
+    /// Token accounts withdraw_collateral and claim_withdrawal may send to, none when empty
+    pub withdrawal_destinations: [Pubkey; 4],
+    /// Destination that can be added once withdrawal_destination_delay has passed since it was
+    /// proposed
+    pub pending_withdrawal_destination: Pubkey,
+    pub pending_withdrawal_destination_ts: i64,
+    /// Part of total_shares owned by the admin, minted by deposit_collateral and burned by
+    /// withdraw_collateral and claim_withdrawal
+    pub admin_shares: u128,
+}
+
+impl Config {
+    /// Fails closed, an empty list allows no destination
+    pub fn is_withdrawal_destination(&self, destination: &Pubkey) -> bool {
+        *destination != Pubkey::default() && self.withdrawal_destinations.contains(destination)
+    }
+
+    /// Seconds between proposing and accepting a withdrawal destination
+    pub fn withdrawal_destination_delay(&self) -> i64 {
+        self.withdrawal_delay.max(MIN_WITHDRAWAL_DESTINATION_DELAY)
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct VaultDepositor {
+    pub config: Pubkey,
+    pub depositor: Pubkey,
+    pub shares: u128,
+}
+
+/// Created by initialize with every market disabled, so open_position is rejected until
+/// update_risk_limits enables markets
+#[account]
+#[derive(Default)]
+pub struct RiskLimits {
+    pub config: Pubkey,
+    /// Max total notional / equity, LEVERAGE_PRECISION
+    pub max_leverage: u128,
+    /// Max distance of the limit price from the oracle price, SLIPPAGE_PRECISION
+    pub max_oracle_slippage: u128,
+    /// Markets open_position is allowed to trade, with their notional caps
+    pub markets: [MarketRiskLimit; 8],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct MarketRiskLimit {
+    pub enabled: bool,
+    pub market_index: u64,
+    /// Max position notional, QUOTE_PRECISION
+    pub max_notional: u128,
+}
+
+/// Checks on the oracle before trading, each disabled when 0
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct OracleGuards {
+    /// Max slots since the oracle price was published
+    pub max_staleness_slots: u64,
+    /// Max oracle confidence / oracle price, ORACLE_GUARD_PRECISION
+    pub max_conf_ratio: u128,
+    /// Max distance of the mark price from the oracle price, ORACLE_GUARD_PRECISION
+    pub max_mark_oracle_divergence: u128,
+}
+
+#[account]
+#[derive(Default)]
+pub struct TriggerOrder {
+    pub config: Pubkey,
+    pub market_index: u64,
+    /// Direction of the position when the trigger was set, the trigger can't execute against a
+    /// flipped position
+    pub direction: PositionDirection,
+    /// Close once the oracle price moves against the position to this price, MARK_PRICE_PRECISION, 0 for none
+    pub stop_loss_price: u128,
+    /// Close once the oracle price moves in favor of the position to this price, MARK_PRICE_PRECISION, 0 for none
+    pub take_profit_price: u128,
+    /// Paid out of the vault to the keeper that executes the trigger, QUOTE_PRECISION
+    pub keeper_fee: u64,
+    /// Raised keeper fee that applies once withdrawal_delay has passed since it was set, 0 for none
+    pub pending_keeper_fee: u64,
+    pub pending_keeper_fee_ts: i64,
+}
+
+#[account]
+#[derive(Default)]
+pub struct OrderBook {
+    pub config: Pubkey,
+    /// Id given to the next order placed, ids start at 1
+    pub next_order_id: u64,
+    /// Resting limit orders, slots with id 0 are empty
+    pub orders: [Order; 16],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Order {
+    pub id: u64,
+    pub direction: PositionDirection,
+    /// QUOTE_PRECISION
+    pub quote_asset_amount: u128,
+    pub market_index: u64,
+    /// Fill once the mark price is at or better than this price, MARK_PRICE_PRECISION
+    pub price: u128,
+    /// Unix timestamp after which the order can no longer be filled, 0 for none
+    pub expiry_ts: i64,
+}
+
+#[account]
+#[derive(Default)]
+pub struct TwapOrder {
+    pub config: Pubkey,
+    pub market_index: u64,
+    pub direction: PositionDirection,
+    /// QUOTE_PRECISION
+    pub total_quote_asset_amount: u128,
+    pub num_slices: u64,
+    /// Seconds between slices
+    pub slice_interval: i64,
+    pub start_ts: i64,
+    pub slices_filled: u64,
+    pub filled_quote_asset_amount: u128,
+    /// Slices executed by crank_twap, in order
+    pub fills: [TwapFill; 16],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct TwapFill {
+    pub ts: i64,
+    pub quote_asset_amount: u128,
+    /// Change in the position's base asset amount, AMM_RESERVE_PRECISION
+    pub base_asset_amount: i128,
+}
+
+#[account]
+#[derive(Default)]
+pub struct WithdrawalRequest {
+    pub config: Pubkey,
+    pub amount: u64,
+    pub request_ts: i64,
 }
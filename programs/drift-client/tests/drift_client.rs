@@ -0,0 +1,3480 @@
+use anchor_lang::__private::base64;
+use anchor_lang::__private::ErrorCode as AnchorErrorCode;
+use anchor_lang::prelude::{AccountMeta, ProgramError, Pubkey};
+use anchor_lang::solana_program::instruction::{Instruction, InstructionError};
+use anchor_lang::solana_program::{system_instruction, system_program, sysvar};
+use anchor_lang::{AccountDeserialize, Event, InstructionData, ToAccountMetas};
+use clearing_house::math::constants::{AMM_RESERVE_PRECISION, MARK_PRICE_PRECISION};
+use clearing_house::state::history::curve::CurveHistory;
+use clearing_house::state::history::deposit::DepositHistory;
+use clearing_house::state::history::funding_payment::FundingPaymentHistory;
+use clearing_house::state::history::funding_rate::FundingRateHistory;
+use clearing_house::state::history::liquidation::LiquidationHistory;
+use clearing_house::state::history::trade::TradeHistory;
+use clearing_house::state::market::Markets;
+use clearing_house::state::state::State;
+use clearing_house::state::user::{User, UserPositions};
+use drift_client::error::ErrorCode;
+use drift_client::events::{
+    ClosePositionEvent, DepositCollateralEvent, InitializeEvent, InitializeUserEvent,
+    OpenPositionEvent, SetTargetPositionEvent, WithdrawCollateralEvent,
+};
+use drift_client::orders::MAX_KEEPER_FEE;
+use drift_client::risk::SLIPPAGE_PRECISION;
+use drift_client::state::{
+    Config, MarketRiskLimit, OracleGuards, MAX_WITHDRAWAL_DELAY, MIN_WITHDRAWAL_DESTINATION_DELAY,
+};
+use drift_client::{PositionDirection, RebalanceLeg};
+use drift_client_sdk::{ClearingHouseAccounts, DriftClient};
+use pyth::pc::{PriceStatus, ProductAttribute};
+use pyth::price_path::PricePoint;
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account;
+use solana_sdk::clock::Clock;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_sdk::transport::TransportError;
+
+const USDC_AMOUNT: u64 = 10_000_000;
+const MARKET_INDEX: u64 = 0;
+// ammInvariant == k == x * y
+const AMM_INITIAL_RESERVES: u128 = 5 * 10_u128.pow(13) * 10_u128.pow(5);
+const AMM_PERIODICITY: i64 = 60 * 60;
+const AMM_PEG_MULTIPLIER: u128 = 1_000;
+const MAX_LEVERAGE: u128 = 50_000; // 5x
+const MAX_ORACLE_SLIPPAGE: u128 = 1_000; // 10%
+
+struct TestContext {
+    context: ProgramTestContext,
+    clearing_house: ClearingHouseAccounts,
+    oracle: Pubkey,
+    admin_collateral_account: Pubkey,
+}
+
+impl TestContext {
+    fn admin(&self) -> Keypair {
+        clone_keypair(&self.context.payer)
+    }
+
+    fn drift_client(&self, sub_account_id: u16) -> DriftClient {
+        DriftClient::new(
+            self.clearing_house,
+            self.context.payer.pubkey(),
+            sub_account_id,
+        )
+    }
+
+    async fn transaction(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<Transaction, TransportError> {
+        let payer = self.admin();
+        let mut all_signers = vec![&payer];
+        all_signers.extend_from_slice(signers);
+        let recent_blockhash = self.context.banks_client.get_recent_blockhash().await?;
+        Ok(Transaction::new_signed_with_payer(
+            instructions,
+            Some(&payer.pubkey()),
+            &all_signers,
+            recent_blockhash,
+        ))
+    }
+
+    async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), TransportError> {
+        let transaction = self.transaction(instructions, signers).await?;
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    /// Processes the instructions and returns the T events they emitted, decoded from the logs of
+    /// a simulation of the same transaction
+    async fn process_with_events<T: Event>(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Vec<T> {
+        let transaction = self.transaction(instructions, signers).await.unwrap();
+        let logs = self
+            .context
+            .banks_client
+            .simulate_transaction(transaction.clone())
+            .await
+            .unwrap()
+            .simulation_details
+            .unwrap()
+            .logs;
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        logs.iter()
+            .filter_map(|log| {
+                log.strip_prefix("Program data: ")
+                    .or_else(|| log.strip_prefix("Program log: "))
+            })
+            .filter_map(|data| base64::decode(data).ok())
+            .filter(|data| data.len() >= 8 && data[..8] == T::discriminator())
+            .map(|data| T::try_from_slice(&data[8..]).unwrap())
+            .collect()
+    }
+
+    async fn get_account(&mut self, address: Pubkey) -> Account {
+        self.context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn get_config(&mut self, drift_client: &DriftClient) -> Config {
+        let account = self.get_account(drift_client.config_address()).await;
+        drift_client_sdk::read_config(&account.data).unwrap()
+    }
+
+    async fn get_user(&mut self, config: &Config) -> User {
+        let account = self.get_account(config.clearing_house_user).await;
+        User::try_deserialize(&mut &account.data[..]).unwrap()
+    }
+
+    async fn get_user_positions(&mut self, config: &Config) -> UserPositions {
+        let account = self.get_account(config.clearing_house_user_positions).await;
+        bytemuck::pod_read_unaligned(&account.data[8..8 + std::mem::size_of::<UserPositions>()])
+    }
+
+    async fn get_token_balance(&mut self, address: Pubkey) -> u64 {
+        let account = self.get_account(address).await;
+        spl_token::state::Account::unpack(&account.data)
+            .unwrap()
+            .amount
+    }
+
+    async fn fund(&mut self, address: Pubkey) {
+        let payer = self.admin().pubkey();
+        self.process(
+            &[system_instruction::transfer(
+                &payer,
+                &address,
+                1_000_000_000,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    }
+
+    /// Moves the clock forward, warping a slot so following transactions get a new blockhash
+    async fn advance_clock(&mut self, seconds: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.slot += 1;
+        clock.unix_timestamp += seconds;
+        self.context.warp_to_slot(clock.slot).unwrap();
+        self.context.set_sysvar(&clock);
+    }
+
+    async fn create_collateral_account(&mut self) -> Pubkey {
+        let admin = self.admin().pubkey();
+        let account = Keypair::new();
+        self.process(
+            &[
+                system_instruction::create_account(
+                    &admin,
+                    &account.pubkey(),
+                    Rent::default().minimum_balance(spl_token::state::Account::LEN),
+                    spl_token::state::Account::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_account(
+                    &spl_token::ID,
+                    &account.pubkey(),
+                    &self.clearing_house.collateral_mint,
+                    &admin,
+                )
+                .unwrap(),
+            ],
+            &[&account],
+        )
+        .await
+        .unwrap();
+        account.pubkey()
+    }
+
+    async fn create_pyth_account(&mut self, size: usize) -> Pubkey {
+        let payer = self.admin().pubkey();
+        let account = Keypair::new();
+        self.process(
+            &[system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                Rent::default().minimum_balance(size),
+                size as u64,
+                &pyth::ID,
+            )],
+            &[&account],
+        )
+        .await
+        .unwrap();
+        account.pubkey()
+    }
+
+    /// Initializes the config and its clearing house user and sets its risk limits
+    async fn initialize_drift_client(&mut self, sub_account_id: u16) -> (DriftClient, Config) {
+        let admin = self.admin();
+        let admin_collateral_account = self.admin_collateral_account;
+        let drift_client = self.drift_client(sub_account_id);
+        let clearing_house_user_positions = Keypair::new();
+        self.process(
+            &[
+                drift_client.initialize(admin.pubkey(), admin_collateral_account),
+                drift_client
+                    .initialize_user(admin.pubkey(), clearing_house_user_positions.pubkey()),
+                drift_client.update_risk_limits(
+                    admin.pubkey(),
+                    MAX_LEVERAGE,
+                    MAX_ORACLE_SLIPPAGE,
+                    market_risk_limits(USDC_AMOUNT as u128 * 5),
+                ),
+            ],
+            &[&clearing_house_user_positions],
+        )
+        .await
+        .unwrap();
+        let config = self.get_config(&drift_client).await;
+        (drift_client, config)
+    }
+
+    async fn deposit_collateral(&mut self, drift_client: &DriftClient, config: &Config) {
+        let admin = self.admin().pubkey();
+        let admin_collateral_account = self.admin_collateral_account;
+        self.process(
+            &[drift_client.deposit_collateral(
+                config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    }
+}
+
+fn clone_keypair(keypair: &Keypair) -> Keypair {
+    Keypair::from_bytes(&keypair.to_bytes()).unwrap()
+}
+
+fn market_risk_limits(max_notional: u128) -> [MarketRiskLimit; 8] {
+    let mut markets = [MarketRiskLimit::default(); 8];
+    markets[0] = MarketRiskLimit {
+        enabled: true,
+        market_index: MARKET_INDEX,
+        max_notional,
+    };
+    markets
+}
+
+fn add_zeroed_account(program_test: &mut ProgramTest, owner: Pubkey, size: usize) -> Pubkey {
+    let address = Pubkey::new_unique();
+    program_test.add_account(
+        address,
+        Account {
+            lamports: Rent::default().minimum_balance(size),
+            data: vec![0; size],
+            owner,
+            ..Account::default()
+        },
+    );
+    address
+}
+
+fn add_zero_copy_account<T>(program_test: &mut ProgramTest) -> Pubkey {
+    add_zeroed_account(
+        program_test,
+        clearing_house::ID,
+        8 + std::mem::size_of::<T>(),
+    )
+}
+
+/// Loads the three programs in-process, then sets up a usdc mint, a funded admin token account,
+/// a clearing house with one market and a mock pyth oracle priced at 1
+async fn setup() -> TestContext {
+    let mut program_test = ProgramTest::new(
+        "drift_client",
+        drift_client::ID,
+        processor!(drift_client::entry),
+    );
+    program_test.add_program("pyth", pyth::ID, processor!(pyth::entry));
+    program_test.add_program(
+        "clearing_house",
+        clearing_house::ID,
+        processor!(clearing_house::entry),
+    );
+
+    let oracle = add_zeroed_account(
+        &mut program_test,
+        pyth::ID,
+        std::mem::size_of::<pyth::pc::Price>(),
+    );
+    let markets = add_zero_copy_account::<Markets>(&mut program_test);
+    let funding_payment_history = add_zero_copy_account::<FundingPaymentHistory>(&mut program_test);
+    let trade_history = add_zero_copy_account::<TradeHistory>(&mut program_test);
+    let liquidation_history = add_zero_copy_account::<LiquidationHistory>(&mut program_test);
+    let deposit_history = add_zero_copy_account::<DepositHistory>(&mut program_test);
+    let funding_rate_history = add_zero_copy_account::<FundingRateHistory>(&mut program_test);
+    let curve_history = add_zero_copy_account::<CurveHistory>(&mut program_test);
+
+    let context = program_test.start_with_context().await;
+    let mut test_context = TestContext {
+        context,
+        clearing_house: ClearingHouseAccounts {
+            program_id: clearing_house::ID,
+            state: Pubkey::default(),
+            collateral_mint: Pubkey::default(),
+            collateral_vault: Pubkey::default(),
+            collateral_vault_authority: Pubkey::default(),
+            insurance_vault: Pubkey::default(),
+            insurance_vault_authority: Pubkey::default(),
+            markets,
+            deposit_history,
+            funding_payment_history,
+            funding_rate_history,
+            trade_history,
+        },
+        oracle,
+        admin_collateral_account: Pubkey::default(),
+    };
+    let admin = test_context.admin();
+
+    // usdc mint and admin token account
+    let usdc_mint = Keypair::new();
+    let admin_collateral_account = Keypair::new();
+    let rent = Rent::default();
+    test_context
+        .process(
+            &[
+                system_instruction::create_account(
+                    &admin.pubkey(),
+                    &usdc_mint.pubkey(),
+                    rent.minimum_balance(spl_token::state::Mint::LEN),
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_mint(
+                    &spl_token::ID,
+                    &usdc_mint.pubkey(),
+                    &admin.pubkey(),
+                    None,
+                    6,
+                )
+                .unwrap(),
+                system_instruction::create_account(
+                    &admin.pubkey(),
+                    &admin_collateral_account.pubkey(),
+                    rent.minimum_balance(spl_token::state::Account::LEN),
+                    spl_token::state::Account::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_account(
+                    &spl_token::ID,
+                    &admin_collateral_account.pubkey(),
+                    &usdc_mint.pubkey(),
+                    &admin.pubkey(),
+                )
+                .unwrap(),
+                spl_token::instruction::mint_to(
+                    &spl_token::ID,
+                    &usdc_mint.pubkey(),
+                    &admin_collateral_account.pubkey(),
+                    &admin.pubkey(),
+                    &[],
+                    USDC_AMOUNT,
+                )
+                .unwrap(),
+            ],
+            &[&usdc_mint, &admin_collateral_account],
+        )
+        .await
+        .unwrap();
+    test_context.admin_collateral_account = admin_collateral_account.pubkey();
+
+    // mock oracle
+    test_context
+        .process(
+            &[Instruction {
+                program_id: pyth::ID,
+                accounts: pyth::accounts::Initialize { price: oracle }.to_account_metas(None),
+                data: pyth::instruction::Initialize {
+                    price: 10_i64.pow(7),
+                    expo: -7,
+                    conf: 0,
+                }
+                .data(),
+            }],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // clearing house
+    let (state, state_nonce) =
+        Pubkey::find_program_address(&[b"clearing_house".as_ref()], &clearing_house::ID);
+    let (collateral_vault, collateral_vault_nonce) =
+        Pubkey::find_program_address(&[b"collateral_vault".as_ref()], &clearing_house::ID);
+    let (collateral_vault_authority, _) =
+        Pubkey::find_program_address(&[collateral_vault.as_ref()], &clearing_house::ID);
+    let (insurance_vault, insurance_vault_nonce) =
+        Pubkey::find_program_address(&[b"insurance_vault".as_ref()], &clearing_house::ID);
+    let (insurance_vault_authority, _) =
+        Pubkey::find_program_address(&[insurance_vault.as_ref()], &clearing_house::ID);
+    let initialize_accounts = clearing_house::accounts::Initialize {
+        admin: admin.pubkey(),
+        state,
+        collateral_mint: usdc_mint.pubkey(),
+        collateral_vault,
+        collateral_vault_authority,
+        insurance_vault,
+        insurance_vault_authority,
+        markets,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+    };
+    let initialize_history_accounts = clearing_house::accounts::InitializeHistory {
+        admin: admin.pubkey(),
+        state,
+        funding_payment_history,
+        trade_history,
+        liquidation_history,
+        deposit_history,
+        funding_rate_history,
+        curve_history,
+    };
+    let initialize_market_accounts = clearing_house::accounts::InitializeMarket {
+        admin: admin.pubkey(),
+        state,
+        markets,
+        oracle,
+    };
+    test_context
+        .process(
+            &[
+                clearing_house_instruction(
+                    initialize_accounts.to_account_metas(None),
+                    clearing_house::instruction::Initialize {
+                        _clearing_house_nonce: state_nonce,
+                        _collateral_vault_nonce: collateral_vault_nonce,
+                        _insurance_vault_nonce: insurance_vault_nonce,
+                        admin_controls_prices: true,
+                    }
+                    .data(),
+                ),
+                clearing_house_instruction(
+                    initialize_history_accounts.to_account_metas(None),
+                    clearing_house::instruction::InitializeHistory {}.data(),
+                ),
+                clearing_house_instruction(
+                    initialize_market_accounts.to_account_metas(None),
+                    clearing_house::instruction::InitializeMarket {
+                        market_index: MARKET_INDEX,
+                        amm_base_asset_reserve: AMM_INITIAL_RESERVES,
+                        amm_quote_asset_reserve: AMM_INITIAL_RESERVES,
+                        amm_periodicity: AMM_PERIODICITY,
+                        amm_peg_multiplier: AMM_PEG_MULTIPLIER,
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let state_account = test_context.get_account(state).await;
+    let state_account = State::try_deserialize(&mut &state_account.data[..]).unwrap();
+    test_context.clearing_house =
+        ClearingHouseAccounts::new(clearing_house::ID, state, &state_account);
+    test_context
+}
+
+fn clearing_house_instruction(accounts: Vec<AccountMeta>, data: Vec<u8>) -> Instruction {
+    Instruction {
+        program_id: clearing_house::ID,
+        accounts,
+        data,
+    }
+}
+
+#[tokio::test]
+async fn initialize() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.owner, admin);
+    assert_eq!(config.sub_account_id, 0);
+    assert_eq!(
+        config.collateral_vault,
+        drift_client.collateral_vault_address()
+    );
+    assert_eq!(config.authority, drift_client.authority_address());
+    assert_eq!(config.risk_limits, drift_client.risk_limits_address());
+    assert_eq!(
+        config.clearing_house_user,
+        drift_client.clearing_house_user_address()
+    );
+
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.authority, config.authority);
+    assert_eq!(user.positions, config.clearing_house_user_positions);
+
+    let risk_limits = test_context.get_account(config.risk_limits).await;
+    let risk_limits = drift_client_sdk::read_risk_limits(&risk_limits.data).unwrap();
+    assert_eq!(risk_limits.max_leverage, MAX_LEVERAGE);
+    assert!(risk_limits.markets[0].enabled);
+    assert!(!risk_limits.markets[1].enabled);
+}
+
+#[tokio::test]
+async fn sub_accounts_are_isolated() {
+    let mut test_context = setup().await;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    let (sub_account_drift_client, sub_account_config) =
+        test_context.initialize_drift_client(1).await;
+
+    assert_eq!(sub_account_config.sub_account_id, 1);
+    assert_ne!(
+        sub_account_drift_client.config_address(),
+        drift_client.config_address()
+    );
+    assert_ne!(sub_account_config.authority, config.authority);
+    assert_ne!(sub_account_config.collateral_vault, config.collateral_vault);
+}
+
+#[tokio::test]
+async fn transfer_admin() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin();
+    let (drift_client, _) = test_context.initialize_drift_client(0).await;
+    let new_admin = Keypair::new();
+    test_context.fund(new_admin.pubkey()).await;
+
+    test_context
+        .process(
+            &[drift_client.propose_admin(admin.pubkey(), new_admin.pubkey())],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.admin, admin.pubkey());
+    assert_eq!(config.pending_admin, new_admin.pubkey());
+
+    test_context
+        .process(
+            &[drift_client.accept_admin(new_admin.pubkey())],
+            &[&new_admin],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.admin, new_admin.pubkey());
+    assert_eq!(config.pending_admin, Pubkey::default());
+}
+
+#[tokio::test]
+async fn update_risk_limits() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    test_context
+        .process(
+            &[drift_client.update_risk_limits(
+                admin,
+                MAX_LEVERAGE * 2,
+                MAX_ORACLE_SLIPPAGE,
+                market_risk_limits(USDC_AMOUNT as u128),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let risk_limits = test_context.get_account(config.risk_limits).await;
+    let risk_limits = drift_client_sdk::read_risk_limits(&risk_limits.data).unwrap();
+    assert_eq!(risk_limits.max_leverage, MAX_LEVERAGE * 2);
+    assert_eq!(risk_limits.markets[0].max_notional, USDC_AMOUNT as u128);
+}
+
+#[tokio::test]
+async fn deposit_and_withdraw_collateral() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.collateral, USDC_AMOUNT as u128);
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        0
+    );
+
+    test_context
+        .process(
+            &[drift_client.withdraw_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.collateral, 0);
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        USDC_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn initialize_emits_events() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let drift_client = test_context.drift_client(0);
+
+    let events = test_context
+        .process_with_events::<InitializeEvent>(
+            &[drift_client.initialize(admin, admin_collateral_account)],
+            &[],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(events[0].config, drift_client.config_address());
+    assert_eq!(events[0].admin, admin);
+    assert_eq!(events[0].owner, admin);
+    assert_eq!(events[0].sub_account_id, 0);
+    assert_eq!(events[0].authority, config.authority);
+    assert_eq!(events[0].collateral_vault, config.collateral_vault);
+
+    let clearing_house_user_positions = Keypair::new();
+    let events = test_context
+        .process_with_events::<InitializeUserEvent>(
+            &[drift_client.initialize_user(admin, clearing_house_user_positions.pubkey())],
+            &[&clearing_house_user_positions],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(events[0].config, drift_client.config_address());
+    assert_eq!(events[0].clearing_house_user, config.clearing_house_user);
+    assert_eq!(
+        events[0].clearing_house_user_positions,
+        clearing_house_user_positions.pubkey()
+    );
+}
+
+#[tokio::test]
+async fn deposit_and_withdraw_collateral_emit_events() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    let events = test_context
+        .process_with_events::<DepositCollateralEvent>(
+            &[drift_client.deposit_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let config = test_context.get_config(&drift_client).await;
+    let user = test_context.get_user(&config).await;
+    assert_eq!(events[0].config, drift_client.config_address());
+    assert_eq!(events[0].depositor, admin);
+    assert_eq!(events[0].amount, USDC_AMOUNT);
+    assert_eq!(events[0].shares, config.admin_shares);
+    assert_eq!(events[0].collateral, user.collateral);
+    assert_eq!(events[0].collateral, USDC_AMOUNT as u128);
+
+    let admin_shares_before = config.admin_shares;
+    let events = test_context
+        .process_with_events::<WithdrawCollateralEvent>(
+            &[drift_client.withdraw_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT / 4,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let config = test_context.get_config(&drift_client).await;
+    let user = test_context.get_user(&config).await;
+    assert_eq!(events[0].depositor, admin);
+    assert_eq!(events[0].amount, USDC_AMOUNT / 4);
+    assert_eq!(events[0].shares, admin_shares_before - config.admin_shares);
+    assert_eq!(events[0].collateral, user.collateral);
+    assert_eq!(
+        events[0].collateral,
+        (USDC_AMOUNT - USDC_AMOUNT / 4) as u128
+    );
+}
+
+#[tokio::test]
+async fn open_and_close_position_emit_events() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let events = test_context
+        .process_with_events::<OpenPositionEvent>(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let user = test_context.get_user(&config).await;
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(events[0].config, drift_client.config_address());
+    assert!(events[0].direction == PositionDirection::Long);
+    assert_eq!(events[0].quote_asset_amount, USDC_AMOUNT as u128);
+    assert_eq!(events[0].market_index, MARKET_INDEX);
+    assert!(!events[0].reduce_only);
+    assert_eq!(events[0].collateral, user.collateral);
+    assert!(events[0].base_asset_amount_after > 0);
+    assert_eq!(
+        events[0].base_asset_amount_after,
+        user_positions.positions[0].base_asset_amount
+    );
+    assert_eq!(
+        events[0].quote_asset_amount_after,
+        user_positions.positions[0].quote_asset_amount
+    );
+
+    let events = test_context
+        .process_with_events::<ClosePositionEvent>(
+            &[drift_client.close_position(&config, admin, oracle, MARKET_INDEX, None, None)],
+            &[],
+        )
+        .await;
+    assert_eq!(events.len(), 1);
+    let user = test_context.get_user(&config).await;
+    assert_eq!(events[0].config, drift_client.config_address());
+    assert_eq!(events[0].market_index, MARKET_INDEX);
+    assert_eq!(events[0].collateral, user.collateral);
+    assert_eq!(events[0].base_asset_amount_after, 0);
+    assert_eq!(events[0].quote_asset_amount_after, 0);
+}
+
+#[tokio::test]
+async fn withdrawal_timelock() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    test_context
+        .process(&[drift_client.update_withdrawal_delay(admin, 100)], &[])
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.withdrawal_delay, 100);
+
+    let result = test_context
+        .process(&[drift_client.update_withdrawal_delay(admin, 50)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDelayDecreased);
+
+    let result = test_context
+        .process(
+            &[drift_client.update_withdrawal_delay(admin, MAX_WITHDRAWAL_DELAY + 1)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDelayTooLong);
+
+    let result = test_context
+        .process(
+            &[drift_client.withdraw_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDelayActive);
+
+    let amount = USDC_AMOUNT / 2;
+    test_context
+        .process(&[drift_client.request_withdrawal(admin, amount)], &[])
+        .await
+        .unwrap();
+    let withdrawal_request = test_context
+        .get_account(drift_client.withdrawal_request_address())
+        .await;
+    let withdrawal_request =
+        drift_client_sdk::read_withdrawal_request(&withdrawal_request.data).unwrap();
+    assert_eq!(withdrawal_request.amount, amount);
+
+    let claim_withdrawal =
+        drift_client.claim_withdrawal(&config, admin, admin_collateral_account, &[]);
+    let result = test_context.process(&[claim_withdrawal.clone()], &[]).await;
+    assert_custom_error(result, ErrorCode::WithdrawalNotClaimable);
+
+    test_context.advance_clock(100).await;
+    test_context
+        .process(&[claim_withdrawal], &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        amount
+    );
+    let withdrawal_request = test_context
+        .context
+        .banks_client
+        .get_account(drift_client.withdrawal_request_address())
+        .await
+        .unwrap();
+    assert!(withdrawal_request.is_none());
+
+    // a cancelled request can't be claimed
+    test_context
+        .process(
+            &[
+                drift_client.request_withdrawal(admin, amount),
+                drift_client.cancel_withdrawal(admin),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    test_context.advance_clock(100).await;
+    let result = test_context
+        .process(
+            &[drift_client.claim_withdrawal(&config, admin, admin_collateral_account, &[])],
+            &[],
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn withdrawal_destination_allow_list() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let other_collateral_account = test_context.create_collateral_account().await;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    test_context
+        .process(
+            &[
+                drift_client.update_withdrawal_delay(admin, 100),
+                drift_client.propose_withdrawal_destination(admin, other_collateral_account),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(&[drift_client.accept_withdrawal_destination(admin)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDestinationNotAcceptable);
+
+    // destinations wait at least MIN_WITHDRAWAL_DESTINATION_DELAY, even past the withdrawal delay
+    test_context.advance_clock(100).await;
+    let result = test_context
+        .process(&[drift_client.accept_withdrawal_destination(admin)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDestinationNotAcceptable);
+
+    test_context
+        .advance_clock(MIN_WITHDRAWAL_DESTINATION_DELAY - 100)
+        .await;
+    test_context
+        .process(&[drift_client.accept_withdrawal_destination(admin)], &[])
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.withdrawal_destinations[0], admin_collateral_account);
+    assert_eq!(config.withdrawal_destinations[1], other_collateral_account);
+    assert_eq!(config.pending_withdrawal_destination, Pubkey::default());
+
+    // the only destination can't be removed
+    test_context
+        .process(
+            &[drift_client.remove_withdrawal_destination(admin, admin_collateral_account)],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(
+            &[drift_client.remove_withdrawal_destination(admin, other_collateral_account)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::LastWithdrawalDestination);
+
+    let amount = USDC_AMOUNT / 2;
+    test_context
+        .process(&[drift_client.request_withdrawal(admin, amount)], &[])
+        .await
+        .unwrap();
+    test_context.advance_clock(100).await;
+    let result = test_context
+        .process(
+            &[drift_client.claim_withdrawal(&config, admin, admin_collateral_account, &[])],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidWithdrawalDestination);
+
+    test_context
+        .process(
+            &[drift_client.claim_withdrawal(&config, admin, other_collateral_account, &[])],
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        test_context
+            .get_token_balance(other_collateral_account)
+            .await,
+        amount
+    );
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn empty_withdrawal_destinations_block_withdrawals() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let drift_client = test_context.drift_client(0);
+    let clearing_house_user_positions = Keypair::new();
+    test_context
+        .process(
+            &[
+                drift_client.initialize(admin, Pubkey::default()),
+                drift_client.initialize_user(admin, clearing_house_user_positions.pubkey()),
+            ],
+            &[&clearing_house_user_positions],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.withdrawal_destinations, [Pubkey::default(); 4]);
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    // even with no withdrawal delay set
+    let result = test_context
+        .process(
+            &[drift_client.withdraw_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidWithdrawalDestination);
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.collateral, USDC_AMOUNT as u128);
+}
+
+#[tokio::test]
+async fn withdrawal_destination_cannot_be_accepted_in_the_proposing_transaction() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let other_collateral_account = test_context.create_collateral_account().await;
+    let (drift_client, _) = test_context.initialize_drift_client(0).await;
+
+    // no withdrawal delay is set
+    let result = test_context
+        .process(
+            &[
+                drift_client.propose_withdrawal_destination(admin, other_collateral_account),
+                drift_client.accept_withdrawal_destination(admin),
+            ],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::WithdrawalDestinationNotAcceptable);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(
+        config.withdrawal_destinations,
+        [
+            admin_collateral_account,
+            Pubkey::default(),
+            Pubkey::default(),
+            Pubkey::default()
+        ]
+    );
+}
+
+#[tokio::test]
+async fn delegate_cannot_withdraw_collateral() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let delegate = Keypair::new();
+    test_context.fund(delegate.pubkey()).await;
+    test_context
+        .process(
+            &[drift_client.update_delegate(admin, delegate.pubkey())],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.delegate, delegate.pubkey());
+
+    let result = test_context
+        .process(
+            &[drift_client.withdraw_collateral(
+                &config,
+                delegate.pubkey(),
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[&delegate],
+        )
+        .await;
+    assert_custom_error(result, AnchorErrorCode::ConstraintHasOne);
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.collateral, USDC_AMOUNT as u128);
+}
+
+#[tokio::test]
+async fn mismatched_clearing_house_user_fails() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    let (_, other_config) = test_context.initialize_drift_client(2).await;
+
+    let mismatched_config = Config {
+        clearing_house_user: other_config.clearing_house_user,
+        clearing_house_user_positions: other_config.clearing_house_user_positions,
+        ..config
+    };
+    let result = test_context
+        .process(
+            &[drift_client.deposit_collateral(
+                &mismatched_config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidClearingHouseUser);
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        USDC_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn mismatched_clearing_house_accounts_fail() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    let (_, other_config) = test_context.initialize_drift_client(2).await;
+
+    let mismatched_config = Config {
+        clearing_house_user_positions: other_config.clearing_house_user_positions,
+        ..config
+    };
+    let result = test_context
+        .process(
+            &[drift_client.deposit_collateral(
+                &mismatched_config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidClearingHouseUserPositions);
+
+    // the vaults and their authorities are swapped
+    let clearing_house = test_context.clearing_house;
+    let swapped_vaults = DriftClient::new(
+        ClearingHouseAccounts {
+            collateral_vault: clearing_house.insurance_vault,
+            insurance_vault: clearing_house.collateral_vault,
+            ..clearing_house
+        },
+        admin,
+        0,
+    );
+    let result = test_context
+        .process(
+            &[swapped_vaults.deposit_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidClearingHouseCollateralVault);
+
+    let swapped_vault_authorities = DriftClient::new(
+        ClearingHouseAccounts {
+            collateral_vault_authority: clearing_house.insurance_vault_authority,
+            insurance_vault_authority: clearing_house.collateral_vault_authority,
+            ..clearing_house
+        },
+        admin,
+        0,
+    );
+    let result = test_context
+        .process(
+            &[swapped_vault_authorities.deposit_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                USDC_AMOUNT,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(
+        result,
+        ErrorCode::InvalidClearingHouseCollateralVaultAuthority,
+    );
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        USDC_AMOUNT
+    );
+
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+    let result = test_context
+        .process(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                admin,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidOracle);
+    let result = test_context
+        .process(
+            &[drift_client.close_position(&config, admin, admin, MARKET_INDEX, None, None)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidOracle);
+
+    test_context
+        .process(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn open_reduce_and_close_position() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let result = test_context
+        .process(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128 * 10,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::MaxMarketNotionalExceeded);
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].quote_asset_amount, 0);
+
+    test_context
+        .process(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128
+    );
+    assert!(user_positions.positions[0].base_asset_amount > 0);
+
+    // flips the position
+    let result = test_context
+        .process(
+            &[drift_client.reduce_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Short,
+                USDC_AMOUNT as u128 * 2,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::ReduceOnlyFlipsPosition);
+
+    // increases the position
+    let result = test_context
+        .process(
+            &[drift_client.reduce_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128 / 2,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::ReduceOnlyIncreasesPosition);
+
+    test_context
+        .process(
+            &[drift_client.reduce_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Short,
+                USDC_AMOUNT as u128 / 2,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert!(user_positions.positions[0].quote_asset_amount < USDC_AMOUNT as u128);
+    assert!(user_positions.positions[0].base_asset_amount > 0);
+
+    test_context
+        .process(
+            &[drift_client.close_position(&config, admin, oracle, MARKET_INDEX, None, None)],
+            &[],
+        )
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].quote_asset_amount, 0);
+    assert_eq!(user_positions.positions[0].base_asset_amount, 0);
+}
+
+#[tokio::test]
+async fn risk_limits_block_trades_until_set_and_let_positions_reduce() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let drift_client = test_context.drift_client(0);
+    let clearing_house_user_positions = Keypair::new();
+    test_context
+        .process(
+            &[
+                drift_client.initialize(admin, admin_collateral_account),
+                drift_client.initialize_user(admin, clearing_house_user_positions.pubkey()),
+            ],
+            &[&clearing_house_user_positions],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.risk_limits, drift_client.risk_limits_address());
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let open_position = |direction: PositionDirection, quote_asset_amount: u128| {
+        drift_client.open_position(
+            &config,
+            admin,
+            oracle,
+            direction,
+            quote_asset_amount,
+            MARKET_INDEX,
+            0,
+            None,
+            None,
+        )
+    };
+    let update_risk_limits = |markets: [MarketRiskLimit; 8]| {
+        drift_client.update_risk_limits(admin, MAX_LEVERAGE, MAX_ORACLE_SLIPPAGE, markets)
+    };
+
+    // every market is disabled until the risk limits are set
+    let result = test_context
+        .process(
+            &[open_position(PositionDirection::Long, USDC_AMOUNT as u128)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::MarketNotAllowed);
+
+    test_context
+        .process(
+            &[
+                update_risk_limits(market_risk_limits(USDC_AMOUNT as u128 * 5)),
+                open_position(PositionDirection::Long, 2 * USDC_AMOUNT as u128),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // the cap is lowered under the position, only trades that shrink it are allowed
+    test_context
+        .process(
+            &[update_risk_limits(market_risk_limits(
+                USDC_AMOUNT as u128 / 2,
+            ))],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(
+            &[open_position(
+                PositionDirection::Long,
+                USDC_AMOUNT as u128 / 2,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::MaxMarketNotionalExceeded);
+    test_context
+        .process(
+            &[open_position(
+                PositionDirection::Short,
+                USDC_AMOUNT as u128 / 2,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    test_context
+        .process(&[update_risk_limits([MarketRiskLimit::default(); 8])], &[])
+        .await
+        .unwrap();
+    test_context
+        .process(
+            &[open_position(
+                PositionDirection::Short,
+                USDC_AMOUNT as u128 / 4,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // flipping the position isn't reducing it
+    let result = test_context
+        .process(
+            &[open_position(
+                PositionDirection::Short,
+                USDC_AMOUNT as u128 * 2,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::MarketNotAllowed);
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert!(user_positions.positions[0].base_asset_amount > 0);
+}
+
+#[tokio::test]
+async fn update_risk_limits_rejects_disabled_guards() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let (drift_client, _) = test_context.initialize_drift_client(0).await;
+
+    let markets = market_risk_limits(USDC_AMOUNT as u128);
+    let result = test_context
+        .process(
+            &[drift_client.update_risk_limits(admin, 0, MAX_ORACLE_SLIPPAGE, markets)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidMaxLeverage);
+
+    let result = test_context
+        .process(
+            &[drift_client.update_risk_limits(admin, MAX_LEVERAGE, SLIPPAGE_PRECISION, markets)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidMaxOracleSlippage);
+
+    test_context
+        .process(
+            &[drift_client.update_risk_limits(
+                admin,
+                MAX_LEVERAGE,
+                SLIPPAGE_PRECISION - 1,
+                markets,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn open_position_respects_oracle_guards() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    test_context
+        .process(
+            &[drift_client.update_oracle_guards(
+                admin,
+                OracleGuards {
+                    max_staleness_slots: 10,
+                    max_conf_ratio: 500,
+                    max_mark_oracle_divergence: 1_000,
+                },
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.oracle_guards.max_staleness_slots, 10);
+
+    let open_long = |quote_asset_amount: u128| {
+        drift_client.open_position(
+            &config,
+            admin,
+            oracle,
+            PositionDirection::Long,
+            quote_asset_amount,
+            MARKET_INDEX,
+            0,
+            None,
+            None,
+        )
+    };
+
+    // stale price
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    test_context
+        .context
+        .warp_to_slot(price.agg.pub_slot + 100)
+        .unwrap();
+    let result = test_context
+        .process(&[open_long(USDC_AMOUNT as u128)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::OracleStale);
+
+    // wide confidence
+    test_context
+        .process(
+            &[
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetPrice {
+                        price: 10_i64.pow(7),
+                    }
+                    .data(),
+                ),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetConf {
+                        conf: 10_u64.pow(6),
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(&[open_long(USDC_AMOUNT as u128 - 1)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::OracleConfidenceTooWide);
+
+    // halted feed
+    test_context
+        .process(
+            &[
+                pyth_instruction(oracle, pyth::instruction::SetConf { conf: 0 }.data()),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetStatus {
+                        status: PriceStatus::Halted,
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(&[open_long(USDC_AMOUNT as u128 - 2)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::OracleNotTrading);
+
+    // mark price diverges from the oracle
+    test_context
+        .process(
+            &[
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetStatus {
+                        status: PriceStatus::Trading,
+                    }
+                    .data(),
+                ),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetPrice {
+                        price: 2 * 10_i64.pow(7),
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(&[open_long(USDC_AMOUNT as u128 - 3)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::MarkOracleDivergenceTooLarge);
+
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].quote_asset_amount, 0);
+
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    test_context
+        .process(&[open_long(USDC_AMOUNT as u128)], &[])
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128
+    );
+}
+
+#[tokio::test]
+async fn trigger_order_closes_position_and_pays_keeper() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let keeper_fee = 10_000;
+    test_context
+        .process(
+            &[
+                drift_client.open_position(
+                    &config,
+                    admin,
+                    oracle,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    0,
+                    None,
+                    None,
+                ),
+                drift_client.initialize_trigger_order(
+                    &config,
+                    admin,
+                    MARKET_INDEX,
+                    9 * MARK_PRICE_PRECISION / 10,
+                    11 * MARK_PRICE_PRECISION / 10,
+                    keeper_fee,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let execute_trigger = |keeper: &Keypair| {
+        drift_client.execute_trigger(
+            &config,
+            keeper.pubkey(),
+            admin_collateral_account,
+            oracle,
+            MARKET_INDEX,
+            &[],
+        )
+    };
+
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[execute_trigger(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::TriggerConditionNotMet);
+
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 85 * 10_i64.pow(5),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let keeper = Keypair::new();
+    test_context
+        .process(&[execute_trigger(&keeper)], &[&keeper])
+        .await
+        .unwrap();
+
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].base_asset_amount, 0);
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        keeper_fee
+    );
+    let trigger_order = test_context
+        .get_account(drift_client.trigger_order_address(MARKET_INDEX))
+        .await;
+    let trigger_order = drift_client_sdk::read_trigger_order(&trigger_order.data).unwrap();
+    assert_eq!(trigger_order.stop_loss_price, 0);
+    assert_eq!(trigger_order.take_profit_price, 0);
+
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[execute_trigger(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::NoPositionToClose);
+}
+
+#[tokio::test]
+async fn trigger_order_rejects_a_flipped_position() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let open_position = |direction: PositionDirection, quote_asset_amount: u128| {
+        drift_client.open_position(
+            &config,
+            admin,
+            oracle,
+            direction,
+            quote_asset_amount,
+            MARKET_INDEX,
+            0,
+            None,
+            None,
+        )
+    };
+    let read_direction = |account: Account| {
+        drift_client_sdk::read_trigger_order(&account.data)
+            .unwrap()
+            .direction
+    };
+
+    // the trigger takes the direction of the position
+    let result = test_context
+        .process(
+            &[drift_client.initialize_trigger_order(
+                &config,
+                admin,
+                MARKET_INDEX,
+                9 * MARK_PRICE_PRECISION / 10,
+                11 * MARK_PRICE_PRECISION / 10,
+                0,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::NoPositionToClose);
+
+    test_context
+        .process(
+            &[
+                open_position(PositionDirection::Long, USDC_AMOUNT as u128),
+                drift_client.initialize_trigger_order(
+                    &config,
+                    admin,
+                    MARKET_INDEX,
+                    9 * MARK_PRICE_PRECISION / 10,
+                    11 * MARK_PRICE_PRECISION / 10,
+                    0,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let trigger_order = test_context
+        .get_account(drift_client.trigger_order_address(MARKET_INDEX))
+        .await;
+    assert!(read_direction(trigger_order) == PositionDirection::Long);
+
+    // flipped to short, the long stop loss would read as a take profit
+    test_context
+        .process(
+            &[
+                open_position(PositionDirection::Short, 2 * USDC_AMOUNT as u128),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetPrice {
+                        price: 85 * 10_i64.pow(5),
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let execute_trigger = |keeper: &Keypair| {
+        drift_client.execute_trigger(
+            &config,
+            keeper.pubkey(),
+            admin_collateral_account,
+            oracle,
+            MARKET_INDEX,
+            &[],
+        )
+    };
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[execute_trigger(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::TriggerDirectionMismatch);
+
+    // updating the trigger sets it against the short
+    test_context
+        .process(
+            &[drift_client.update_trigger_order(
+                &config,
+                admin,
+                MARKET_INDEX,
+                11 * MARK_PRICE_PRECISION / 10,
+                9 * MARK_PRICE_PRECISION / 10,
+                0,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let trigger_order = test_context
+        .get_account(drift_client.trigger_order_address(MARKET_INDEX))
+        .await;
+    assert!(read_direction(trigger_order) == PositionDirection::Short);
+    let keeper = Keypair::new();
+    test_context
+        .process(&[execute_trigger(&keeper)], &[&keeper])
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].base_asset_amount, 0);
+}
+
+#[tokio::test]
+async fn keeper_fee_is_capped_and_raises_wait_for_the_withdrawal_delay() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .process(
+            &[
+                drift_client.deposit_collateral(
+                    &config,
+                    admin,
+                    admin_collateral_account,
+                    USDC_AMOUNT / 2,
+                    &[],
+                ),
+                drift_client.initialize_vault_depositor(admin),
+                drift_client.vault_deposit(
+                    &config,
+                    admin,
+                    admin_collateral_account,
+                    USDC_AMOUNT / 2,
+                    &[],
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let stop_loss_price = 9 * MARK_PRICE_PRECISION / 10;
+    let take_profit_price = 11 * MARK_PRICE_PRECISION / 10;
+    test_context
+        .process(
+            &[
+                drift_client.update_withdrawal_delay(admin, 100),
+                drift_client.open_position(
+                    &config,
+                    admin,
+                    oracle,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    0,
+                    None,
+                    None,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let result = test_context
+        .process(
+            &[drift_client.initialize_trigger_order(
+                &config,
+                admin,
+                MARKET_INDEX,
+                stop_loss_price,
+                take_profit_price,
+                MAX_KEEPER_FEE + 1,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::KeeperFeeTooLarge);
+
+    test_context
+        .process(
+            &[drift_client.initialize_trigger_order(
+                &config,
+                admin,
+                MARKET_INDEX,
+                stop_loss_price,
+                take_profit_price,
+                MAX_KEEPER_FEE,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let trigger_order = test_context
+        .get_account(drift_client.trigger_order_address(MARKET_INDEX))
+        .await;
+    let trigger_order = drift_client_sdk::read_trigger_order(&trigger_order.data).unwrap();
+    assert_eq!(trigger_order.keeper_fee, 0);
+    assert_eq!(trigger_order.pending_keeper_fee, MAX_KEEPER_FEE);
+
+    let result = test_context
+        .process(
+            &[drift_client.update_trigger_order(
+                &config,
+                admin,
+                MARKET_INDEX,
+                stop_loss_price,
+                take_profit_price,
+                MAX_KEEPER_FEE + 1,
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::KeeperFeeTooLarge);
+
+    // the raise applies once the withdrawal delay has passed
+    test_context.advance_clock(100).await;
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 85 * 10_i64.pow(5),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    let balance_before = test_context
+        .get_token_balance(admin_collateral_account)
+        .await;
+    let keeper = Keypair::new();
+    test_context
+        .process(
+            &[drift_client.execute_trigger(
+                &config,
+                keeper.pubkey(),
+                admin_collateral_account,
+                oracle,
+                MARKET_INDEX,
+                &[],
+            )],
+            &[&keeper],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        balance_before + MAX_KEEPER_FEE
+    );
+
+    // the fee is burned from the admin shares, the share price is what it was before the fee up
+    // to rounding in the depositors' favor, compared cross multiplied
+    let config_after = test_context.get_config(&drift_client).await;
+    assert_eq!(
+        config_after.total_shares - config_after.admin_shares,
+        config.total_shares - config.admin_shares
+    );
+    assert!(config_after.admin_shares < config.admin_shares);
+    let equity_after = test_context.get_user(&config).await.collateral;
+    let equity_before_fee = equity_after + MAX_KEEPER_FEE as u128;
+    let price_after = equity_after * config.total_shares;
+    let price_before_fee = equity_before_fee * config_after.total_shares;
+    assert!(price_after >= price_before_fee);
+    assert!(price_after - price_before_fee < equity_before_fee);
+
+    // lowering the fee applies at once
+    test_context
+        .process(
+            &[
+                drift_client.open_position(
+                    &config,
+                    admin,
+                    oracle,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    0,
+                    None,
+                    None,
+                ),
+                drift_client.update_trigger_order(
+                    &config,
+                    admin,
+                    MARKET_INDEX,
+                    stop_loss_price,
+                    take_profit_price,
+                    MAX_KEEPER_FEE / 2,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let trigger_order = test_context
+        .get_account(drift_client.trigger_order_address(MARKET_INDEX))
+        .await;
+    let trigger_order = drift_client_sdk::read_trigger_order(&trigger_order.data).unwrap();
+    assert_eq!(trigger_order.keeper_fee, MAX_KEEPER_FEE / 2);
+    assert_eq!(trigger_order.pending_keeper_fee, 0);
+}
+
+#[tokio::test]
+async fn limit_orders_fill_once_mark_price_crosses() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    // the mark price starts at 1
+    test_context
+        .process(
+            &[
+                drift_client.initialize_order_book(admin),
+                drift_client.place_order(
+                    admin,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    MARK_PRICE_PRECISION / 2,
+                    0,
+                ),
+                drift_client.place_order(
+                    admin,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    105 * MARK_PRICE_PRECISION / 100,
+                    0,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let order_book = test_context
+        .get_account(drift_client.order_book_address())
+        .await;
+    let order_book = drift_client_sdk::read_order_book(&order_book.data).unwrap();
+    assert_eq!(order_book.orders[0].id, 1);
+    assert_eq!(order_book.orders[1].id, 2);
+    assert_eq!(order_book.next_order_id, 3);
+
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(
+            &[drift_client.fill_order(&config, keeper.pubkey(), oracle, 1)],
+            &[&keeper],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::LimitPriceNotCrossed);
+
+    test_context
+        .process(
+            &[drift_client.fill_order(&config, keeper.pubkey(), oracle, 2)],
+            &[&keeper],
+        )
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128
+    );
+
+    // filled orders are removed from the book
+    let result = test_context
+        .process(&[drift_client.fill_order(&config, admin, oracle, 2)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::OrderNotFound);
+
+    test_context
+        .process(&[drift_client.cancel_order(admin, 1)], &[])
+        .await
+        .unwrap();
+    let order_book = test_context
+        .get_account(drift_client.order_book_address())
+        .await;
+    let order_book = drift_client_sdk::read_order_book(&order_book.data).unwrap();
+    assert!(order_book.orders.iter().all(|order| order.id == 0));
+}
+
+#[tokio::test]
+async fn start_twap_rejects_invalid_parameters() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let (drift_client, _) = test_context.initialize_drift_client(0).await;
+    test_context
+        .process(
+            &[drift_client.initialize_twap_order(admin, MARKET_INDEX)],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let start_twap = |total_quote_asset_amount: u128, num_slices: u64, duration: i64| {
+        drift_client.start_twap(
+            admin,
+            MARKET_INDEX,
+            PositionDirection::Long,
+            total_quote_asset_amount,
+            num_slices,
+            duration,
+        )
+    };
+
+    let result = test_context
+        .process(&[start_twap(USDC_AMOUNT as u128, 2, -1)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidTwapDuration);
+
+    let result = test_context
+        .process(&[start_twap(USDC_AMOUNT as u128, 0, 100)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidTwapSlices);
+
+    let result = test_context
+        .process(&[start_twap(USDC_AMOUNT as u128, 17, 100)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidTwapSlices);
+
+    let result = test_context.process(&[start_twap(3, 4, 100)], &[]).await;
+    assert_custom_error(result, ErrorCode::TwapSliceTooSmall);
+
+    test_context
+        .process(&[start_twap(4, 4, 100)], &[])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn twap_order_fills_slices_over_time() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    test_context
+        .process(
+            &[
+                drift_client.initialize_twap_order(admin, MARKET_INDEX),
+                drift_client.start_twap(
+                    admin,
+                    MARKET_INDEX,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    2,
+                    100,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let crank_twap =
+        |keeper: &Keypair| drift_client.crank_twap(&config, keeper.pubkey(), oracle, MARKET_INDEX);
+
+    // the first slice is due right away
+    let keeper = Keypair::new();
+    test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128 / 2
+    );
+
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::TwapSliceNotDue);
+
+    test_context.advance_clock(50).await;
+    let keeper = Keypair::new();
+    test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128
+    );
+
+    let twap_order = test_context
+        .get_account(drift_client.twap_order_address(MARKET_INDEX))
+        .await;
+    let twap_order = drift_client_sdk::read_twap_order(&twap_order.data).unwrap();
+    assert_eq!(twap_order.slices_filled, 2);
+    assert_eq!(twap_order.filled_quote_asset_amount, USDC_AMOUNT as u128);
+    assert_eq!(twap_order.fills[1].ts - twap_order.fills[0].ts, 50);
+    assert_eq!(
+        twap_order.fills[0].base_asset_amount + twap_order.fills[1].base_asset_amount,
+        user_positions.positions[0].base_asset_amount
+    );
+
+    test_context.advance_clock(50).await;
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::TwapComplete);
+}
+
+#[tokio::test]
+async fn cancel_twap_keeps_filled_slices() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    test_context
+        .process(
+            &[
+                drift_client.initialize_twap_order(admin, MARKET_INDEX),
+                drift_client.start_twap(
+                    admin,
+                    MARKET_INDEX,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128,
+                    4,
+                    100,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    let crank_twap =
+        |keeper: &Keypair| drift_client.crank_twap(&config, keeper.pubkey(), oracle, MARKET_INDEX);
+    let keeper = Keypair::new();
+    test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await
+        .unwrap();
+    test_context
+        .process(&[drift_client.cancel_twap(admin, MARKET_INDEX)], &[])
+        .await
+        .unwrap();
+
+    let twap_order = test_context
+        .get_account(drift_client.twap_order_address(MARKET_INDEX))
+        .await;
+    let twap_order = drift_client_sdk::read_twap_order(&twap_order.data).unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(twap_order.num_slices, 1);
+    assert_eq!(twap_order.slices_filled, 1);
+    assert_eq!(
+        twap_order.filled_quote_asset_amount,
+        USDC_AMOUNT as u128 / 4
+    );
+    assert_eq!(
+        twap_order.fills[0].quote_asset_amount,
+        USDC_AMOUNT as u128 / 4
+    );
+    assert_eq!(
+        twap_order.fills[0].base_asset_amount,
+        user_positions.positions[0].base_asset_amount
+    );
+
+    // the next slice would have been due
+    test_context.advance_clock(25).await;
+    let keeper = Keypair::new();
+    let result = test_context
+        .process(&[crank_twap(&keeper)], &[&keeper])
+        .await;
+    assert_custom_error(result, ErrorCode::TwapComplete);
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].quote_asset_amount,
+        USDC_AMOUNT as u128 / 4
+    );
+}
+
+#[tokio::test]
+async fn rebalance_applies_all_legs_or_none() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let leg = |direction: PositionDirection, quote_asset_amount: u128| RebalanceLeg {
+        market_index: MARKET_INDEX,
+        direction,
+        quote_asset_amount,
+    };
+
+    // the second leg exceeds the market's notional limit
+    let result = test_context
+        .process(
+            &[drift_client.rebalance(
+                &config,
+                admin,
+                vec![
+                    leg(PositionDirection::Long, 3 * USDC_AMOUNT as u128),
+                    leg(PositionDirection::Long, 10 * USDC_AMOUNT as u128),
+                ],
+                &[oracle, oracle],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::MaxMarketNotionalExceeded);
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(user_positions.positions[0].base_asset_amount, 0);
+
+    let legs = vec![
+        leg(PositionDirection::Long, 3 * USDC_AMOUNT as u128),
+        leg(PositionDirection::Short, USDC_AMOUNT as u128),
+    ];
+
+    // one oracle per leg
+    let result = test_context
+        .process(
+            &[drift_client.rebalance(&config, admin, legs.clone(), &[oracle])],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidRebalanceOracles);
+
+    // the oracle must be the leg's market oracle
+    let result = test_context
+        .process(
+            &[drift_client.rebalance(&config, admin, legs.clone(), &[oracle, admin])],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidOracle);
+
+    test_context
+        .process(
+            &[drift_client.rebalance(&config, admin, legs, &[oracle, oracle])],
+            &[],
+        )
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert!(user_positions.positions[0].base_asset_amount > 0);
+    assert!(user_positions.positions[0].quote_asset_amount < 3 * USDC_AMOUNT as u128);
+    assert!(user_positions.positions[0].quote_asset_amount > USDC_AMOUNT as u128);
+}
+
+#[tokio::test]
+async fn set_target_position_opens_reduces_flips_and_closes() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    // at a mark price of 1, one base unit is worth one dollar
+    let base_unit = AMM_RESERVE_PRECISION as i128;
+    let max_slippage = 100; // 1%
+    for (i, target) in [2 * base_unit, base_unit, -base_unit, 0, 0]
+        .iter()
+        .enumerate()
+    {
+        // a different slippage per step keeps the transactions distinct
+        test_context
+            .process(
+                &[drift_client.set_target_position(
+                    &config,
+                    admin,
+                    oracle,
+                    MARKET_INDEX,
+                    *target,
+                    max_slippage + i as u128,
+                )],
+                &[],
+            )
+            .await
+            .unwrap();
+        let user_positions = test_context.get_user_positions(&config).await;
+        let base_asset_amount = user_positions.positions[0].base_asset_amount;
+        assert!((base_asset_amount - target).abs() <= target.abs() / 100);
+        assert_eq!(base_asset_amount.signum(), target.signum());
+    }
+}
+
+#[tokio::test]
+async fn set_target_position_rejects_invalid_trades() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    test_context
+        .deposit_collateral(&drift_client, &config)
+        .await;
+
+    let base_unit = AMM_RESERVE_PRECISION as i128;
+    // a different slippage per call keeps the transactions distinct
+    let set_target_position = |trader: Pubkey, target: i128, max_slippage: u128| {
+        drift_client.set_target_position(
+            &config,
+            trader,
+            oracle,
+            MARKET_INDEX,
+            target,
+            max_slippage,
+        )
+    };
+
+    let stranger = Keypair::new();
+    let result = test_context
+        .process(
+            &[set_target_position(stranger.pubkey(), base_unit, 100)],
+            &[&stranger],
+        )
+        .await;
+    assert_custom_error(result, AnchorErrorCode::ConstraintRaw);
+
+    // the market cap is 5 times the deposit, worth 50 base units
+    let result = test_context
+        .process(&[set_target_position(admin, 60 * base_unit, 100)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::MaxMarketNotionalExceeded);
+
+    test_context
+        .process(&[set_target_position(admin, 2 * base_unit, 101)], &[])
+        .await
+        .unwrap();
+    let result = test_context
+        .process(&[set_target_position(admin, -60 * base_unit, 102)], &[])
+        .await;
+    assert_custom_error(result, ErrorCode::MaxMarketNotionalExceeded);
+    let user_positions = test_context.get_user_positions(&config).await;
+    let base_asset_amount = user_positions.positions[0].base_asset_amount;
+    assert!(base_asset_amount > 0);
+
+    // already on target, nothing is traded and no event is emitted
+    let events = test_context
+        .process_with_events::<SetTargetPositionEvent>(
+            &[set_target_position(admin, base_asset_amount, 103)],
+            &[],
+        )
+        .await;
+    assert!(events.is_empty());
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert_eq!(
+        user_positions.positions[0].base_asset_amount,
+        base_asset_amount
+    );
+
+    let result = test_context
+        .process(
+            &[set_target_position(admin, base_asset_amount + 1, 104)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::TargetTradeTooSmall);
+
+    // reducing to a sliver of the position isn't read as a flip
+    test_context
+        .process(&[set_target_position(admin, 1, 105)], &[])
+        .await
+        .unwrap();
+    let user_positions = test_context.get_user_positions(&config).await;
+    assert!(user_positions.positions[0].base_asset_amount >= 0);
+    assert!(user_positions.positions[0].base_asset_amount < base_asset_amount / 100);
+}
+
+#[tokio::test]
+async fn vault_deposit_and_withdraw() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    let deposit_amount = USDC_AMOUNT / 2;
+    test_context
+        .process(
+            &[
+                drift_client.initialize_vault_depositor(admin),
+                drift_client.vault_deposit(
+                    &config,
+                    admin,
+                    admin_collateral_account,
+                    deposit_amount,
+                    &[],
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let vault_depositor_address = drift_client.vault_depositor_address(&admin);
+    let vault_depositor = test_context.get_account(vault_depositor_address).await;
+    let vault_depositor = drift_client_sdk::read_vault_depositor(&vault_depositor.data).unwrap();
+    assert_eq!(vault_depositor.shares, deposit_amount as u128);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.total_shares, deposit_amount as u128);
+    let user = test_context.get_user(&config).await;
+    assert_eq!(user.collateral, deposit_amount as u128);
+
+    test_context
+        .process(
+            &[drift_client.vault_withdraw(
+                &config,
+                admin,
+                admin_collateral_account,
+                vault_depositor.shares,
+                &[],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let vault_depositor = test_context.get_account(vault_depositor_address).await;
+    let vault_depositor = drift_client_sdk::read_vault_depositor(&vault_depositor.data).unwrap();
+    assert_eq!(vault_depositor.shares, 0);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.total_shares, 0);
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        USDC_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn vault_shares_price_admin_deposits_and_pnl() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+
+    let admin_amount = USDC_AMOUNT / 2;
+    test_context
+        .process(
+            &[drift_client.deposit_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                admin_amount,
+                &[],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.admin_shares, admin_amount as u128);
+    assert_eq!(config.total_shares, admin_amount as u128);
+
+    // the depositor joins at the price of the admin's shares instead of claiming their equity
+    let deposit_amount = USDC_AMOUNT / 4;
+    test_context
+        .process(
+            &[
+                drift_client.initialize_vault_depositor(admin),
+                drift_client.vault_deposit(
+                    &config,
+                    admin,
+                    admin_collateral_account,
+                    deposit_amount,
+                    &[],
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let vault_depositor_address = drift_client.vault_depositor_address(&admin);
+    let vault_depositor = test_context.get_account(vault_depositor_address).await;
+    let vault_depositor = drift_client_sdk::read_vault_depositor(&vault_depositor.data).unwrap();
+    assert_eq!(vault_depositor.shares, deposit_amount as u128);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.total_shares, (admin_amount + deposit_amount) as u128);
+
+    // fees and price impact leave the vault with less equity than its shares
+    test_context
+        .process(
+            &[drift_client.open_position(
+                &config,
+                admin,
+                oracle,
+                PositionDirection::Long,
+                USDC_AMOUNT as u128 / 2,
+                MARKET_INDEX,
+                0,
+                None,
+                None,
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    test_context
+        .process(
+            &[drift_client.vault_deposit(
+                &config,
+                admin,
+                admin_collateral_account,
+                deposit_amount,
+                &[oracle],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let vault_depositor = test_context.get_account(vault_depositor_address).await;
+    let vault_depositor = drift_client_sdk::read_vault_depositor(&vault_depositor.data).unwrap();
+    assert!(vault_depositor.shares > 2 * deposit_amount as u128);
+
+    // the admin's shares no longer back their whole deposit
+    let result = test_context
+        .process(
+            &[drift_client.withdraw_collateral(
+                &config,
+                admin,
+                admin_collateral_account,
+                admin_amount,
+                &[oracle],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InsufficientShares);
+
+    test_context
+        .process(
+            &[drift_client.vault_withdraw(
+                &config,
+                admin,
+                admin_collateral_account,
+                vault_depositor.shares,
+                &[oracle],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let withdrawn = test_context
+        .get_token_balance(admin_collateral_account)
+        .await;
+    assert!(withdrawn > 0);
+    assert!(withdrawn < 2 * deposit_amount);
+    let config = test_context.get_config(&drift_client).await;
+    assert_eq!(config.admin_shares, admin_amount as u128);
+    assert_eq!(config.total_shares, config.admin_shares);
+
+    // deposits too small to mint a share are rejected
+    let result = test_context
+        .process(
+            &[drift_client.vault_deposit(&config, admin, admin_collateral_account, 0, &[oracle])],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::AmountTooSmall);
+}
+
+#[tokio::test]
+async fn vault_shares_are_priced_at_the_oracle() {
+    let mut test_context = setup().await;
+    let admin = test_context.admin().pubkey();
+    let oracle = test_context.oracle;
+    let admin_collateral_account = test_context.admin_collateral_account;
+    let (drift_client, config) = test_context.initialize_drift_client(0).await;
+    let (other_drift_client, other_config) = test_context.initialize_drift_client(1).await;
+
+    test_context
+        .process(
+            &[
+                drift_client.deposit_collateral(
+                    &config,
+                    admin,
+                    admin_collateral_account,
+                    USDC_AMOUNT / 4,
+                    &[],
+                ),
+                drift_client.open_position(
+                    &config,
+                    admin,
+                    oracle,
+                    PositionDirection::Long,
+                    USDC_AMOUNT as u128 / 2,
+                    MARKET_INDEX,
+                    0,
+                    None,
+                    None,
+                ),
+                drift_client.initialize_vault_depositor(admin),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let config = test_context.get_config(&drift_client).await;
+
+    // the open position's oracle is required
+    let deposit_amount = USDC_AMOUNT / 4;
+    let result = test_context
+        .process(
+            &[drift_client.vault_deposit(
+                &config,
+                admin,
+                admin_collateral_account,
+                deposit_amount,
+                &[],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidEquityOracles);
+
+    let result = test_context
+        .process(
+            &[drift_client.vault_deposit(
+                &config,
+                admin,
+                admin_collateral_account,
+                deposit_amount,
+                &[admin_collateral_account],
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, ErrorCode::InvalidOracle);
+
+    test_context
+        .process(
+            &[drift_client.vault_deposit(
+                &config,
+                admin,
+                admin_collateral_account,
+                deposit_amount,
+                &[oracle],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let vault_depositor_address = drift_client.vault_depositor_address(&admin);
+    let vault_depositor = test_context.get_account(vault_depositor_address).await;
+    let vault_depositor = drift_client_sdk::read_vault_depositor(&vault_depositor.data).unwrap();
+
+    // another account pushes the mark price above the oracle, which would put the vault's long
+    // in profit at the mark price
+    test_context
+        .process(
+            &[
+                other_drift_client.deposit_collateral(
+                    &other_config,
+                    admin,
+                    admin_collateral_account,
+                    USDC_AMOUNT / 2,
+                    &[],
+                ),
+                other_drift_client.open_position(
+                    &other_config,
+                    admin,
+                    oracle,
+                    PositionDirection::Long,
+                    2 * USDC_AMOUNT as u128,
+                    MARKET_INDEX,
+                    0,
+                    None,
+                    None,
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        test_context
+            .get_token_balance(admin_collateral_account)
+            .await,
+        0
+    );
+
+    test_context
+        .process(
+            &[drift_client.vault_withdraw(
+                &config,
+                admin,
+                admin_collateral_account,
+                vault_depositor.shares,
+                &[oracle],
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let withdrawn = test_context
+        .get_token_balance(admin_collateral_account)
+        .await;
+    assert!(withdrawn > 0);
+    assert!(withdrawn <= deposit_amount);
+}
+
+#[tokio::test]
+async fn pyth_set_price() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 2 * 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.agg.price, 2 * 10_i64.pow(7));
+    assert_eq!(price.expo, -7);
+}
+
+#[tokio::test]
+async fn pyth_set_conf_status_and_slots() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+
+    test_context
+        .process(
+            &[
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetConf {
+                        conf: 10_u64.pow(6),
+                    }
+                    .data(),
+                ),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetStatus {
+                        status: PriceStatus::Halted,
+                    }
+                    .data(),
+                ),
+                pyth_instruction(
+                    oracle,
+                    pyth::instruction::SetSlots {
+                        pub_slot: 1,
+                        valid_slot: 2,
+                    }
+                    .data(),
+                ),
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.agg.conf, 10_u64.pow(6));
+    assert_eq!(price.agg.status, PriceStatus::Halted);
+    assert_eq!(price.agg.pub_slot, 1);
+    assert_eq!(price.valid_slot, 2);
+}
+
+#[tokio::test]
+async fn pyth_twap_is_slot_weighted() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 2 * 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.twap, 10_i64.pow(7));
+
+    // the new price is held for half of the twap period
+    test_context
+        .context
+        .warp_to_slot(price.curr_slot + pyth::pc::TWAP_PERIOD / 2)
+        .unwrap();
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetPrice {
+                    price: 3 * 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.twap, 15 * 10_i64.pow(6));
+
+    test_context
+        .process(
+            &[pyth_instruction(
+                oracle,
+                pyth::instruction::SetTwap {
+                    twap: 4 * 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await
+        .unwrap();
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.twap, 4 * 10_i64.pow(7));
+}
+
+#[tokio::test]
+async fn pyth_mapping_product_and_price() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+    let mapping = test_context
+        .create_pyth_account(std::mem::size_of::<pyth::pc::Mapping>())
+        .await;
+    let product = test_context
+        .create_pyth_account(std::mem::size_of::<pyth::pc::Product>())
+        .await;
+    let attributes = vec![
+        ProductAttribute {
+            key: "symbol".to_string(),
+            value: "SOL/USD".to_string(),
+        },
+        ProductAttribute {
+            key: "asset_type".to_string(),
+            value: "Crypto".to_string(),
+        },
+    ];
+
+    test_context
+        .process(
+            &[
+                Instruction {
+                    program_id: pyth::ID,
+                    accounts: pyth::accounts::InitializeMapping { mapping }.to_account_metas(None),
+                    data: pyth::instruction::InitializeMapping {}.data(),
+                },
+                Instruction {
+                    program_id: pyth::ID,
+                    accounts: pyth::accounts::InitializeProduct { product }.to_account_metas(None),
+                    data: pyth::instruction::InitializeProduct {
+                        attributes: attributes.clone(),
+                    }
+                    .data(),
+                },
+                Instruction {
+                    program_id: pyth::ID,
+                    accounts: pyth::accounts::AddProduct { mapping, product }
+                        .to_account_metas(None),
+                    data: pyth::instruction::AddProduct {}.data(),
+                },
+                Instruction {
+                    program_id: pyth::ID,
+                    accounts: pyth::accounts::AddPrice {
+                        product,
+                        price: oracle,
+                    }
+                    .to_account_metas(None),
+                    data: pyth::instruction::AddPrice {}.data(),
+                },
+            ],
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // walk mapping -> product -> price
+    let account = test_context.get_account(mapping).await;
+    let mapping_account: pyth::pc::Mapping = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(mapping_account.magic, pyth::pc::MAGIC);
+    assert_eq!(mapping_account.atype, pyth::pc::AccountType::Mapping as u32);
+    assert_eq!(mapping_account.num, 1);
+    assert_eq!(Pubkey::new(&mapping_account.products[0].val), product);
+
+    let account = test_context.get_account(product).await;
+    let product_account: pyth::pc::Product = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(product_account.atype, pyth::pc::AccountType::Product as u32);
+    assert_eq!(product_account.attributes(), attributes);
+    assert_eq!(Pubkey::new(&product_account.px_acc.val), oracle);
+
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(Pubkey::new(&price.prod.val), product);
+}
+
+#[tokio::test]
+async fn pyth_links_mappings() {
+    let mut test_context = setup().await;
+    let mapping_size = std::mem::size_of::<pyth::pc::Mapping>();
+    let mapping = test_context.create_pyth_account(mapping_size).await;
+    let next_mapping = test_context.create_pyth_account(mapping_size).await;
+    let uninitialized_mapping = test_context.create_pyth_account(mapping_size).await;
+    let set_next_mapping = |mapping: Pubkey, next_mapping: Pubkey| Instruction {
+        program_id: pyth::ID,
+        accounts: pyth::accounts::SetNextMapping {
+            mapping,
+            next_mapping,
+        }
+        .to_account_metas(None),
+        data: pyth::instruction::SetNextMapping {}.data(),
+    };
+
+    let initialize_mappings: Vec<Instruction> = [mapping, next_mapping]
+        .iter()
+        .map(|mapping| Instruction {
+            program_id: pyth::ID,
+            accounts: pyth::accounts::InitializeMapping { mapping: *mapping }
+                .to_account_metas(None),
+            data: pyth::instruction::InitializeMapping {}.data(),
+        })
+        .collect();
+    test_context
+        .process(&initialize_mappings, &[])
+        .await
+        .unwrap();
+    test_context
+        .process(&[set_next_mapping(mapping, next_mapping)], &[])
+        .await
+        .unwrap();
+
+    let account = test_context.get_account(mapping).await;
+    let mapping_account: pyth::pc::Mapping = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(Pubkey::new(&mapping_account.next.val), next_mapping);
+    let account = test_context.get_account(next_mapping).await;
+    let next_mapping_account: pyth::pc::Mapping = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(
+        Pubkey::new(&next_mapping_account.next.val),
+        Pubkey::default()
+    );
+
+    let result = test_context
+        .process(
+            &[set_next_mapping(uninitialized_mapping, next_mapping)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, pyth::ErrorCode::InvalidMagic);
+
+    // a mapping sized account the pyth program doesn't own
+    let payer = test_context.admin().pubkey();
+    let foreign_mapping = Keypair::new();
+    test_context
+        .process(
+            &[system_instruction::create_account(
+                &payer,
+                &foreign_mapping.pubkey(),
+                Rent::default().minimum_balance(mapping_size),
+                mapping_size as u64,
+                &system_program::ID,
+            )],
+            &[&foreign_mapping],
+        )
+        .await
+        .unwrap();
+    let result = test_context
+        .process(
+            &[set_next_mapping(foreign_mapping.pubkey(), next_mapping)],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, pyth::ErrorCode::InvalidAccountOwner);
+}
+
+#[tokio::test]
+async fn pyth_aggregates_publisher_prices() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+    let publishers = [Keypair::new(), Keypair::new(), Keypair::new()];
+
+    let add_publishers: Vec<Instruction> = publishers
+        .iter()
+        .map(|publisher| {
+            pyth_instruction(
+                oracle,
+                pyth::instruction::AddPublisher {
+                    publisher: publisher.pubkey(),
+                }
+                .data(),
+            )
+        })
+        .collect();
+    test_context.process(&add_publishers, &[]).await.unwrap();
+
+    // the third publisher submits a bad price
+    for (publisher, price) in publishers.iter().zip([100, 101, 1_000].iter()) {
+        test_context
+            .process(
+                &[submit_price_instruction(oracle, publisher, *price, 1)],
+                &[publisher],
+            )
+            .await
+            .unwrap();
+    }
+
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.num, 3);
+    assert_eq!(price.comp[2].agg.price, 1_000);
+    assert_eq!(price.agg.price, 101);
+    assert_eq!(price.agg.conf, 898);
+    assert_eq!(Pubkey::new(&price.agg_pub.val), publishers[2].pubkey());
+
+    // unknown publishers are rejected
+    let unknown_publisher = Keypair::new();
+    let result = test_context
+        .process(
+            &[submit_price_instruction(oracle, &unknown_publisher, 100, 1)],
+            &[&unknown_publisher],
+        )
+        .await;
+    assert_custom_error(result, pyth::ErrorCode::UnknownPublisher);
+}
+
+#[tokio::test]
+async fn pyth_aggregate_averages_middle_prices_and_skips_stale_publishers() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+    let publishers = [Keypair::new(), Keypair::new(), Keypair::new()];
+
+    let add_publishers: Vec<Instruction> = publishers
+        .iter()
+        .map(|publisher| {
+            pyth_instruction(
+                oracle,
+                pyth::instruction::AddPublisher {
+                    publisher: publisher.pubkey(),
+                }
+                .data(),
+            )
+        })
+        .collect();
+    test_context.process(&add_publishers, &[]).await.unwrap();
+
+    // the first publisher goes quiet after a bad price
+    test_context
+        .process(
+            &[submit_price_instruction(oracle, &publishers[0], 1_000, 0)],
+            &[&publishers[0]],
+        )
+        .await
+        .unwrap();
+    let clock: Clock = test_context
+        .context
+        .banks_client
+        .get_sysvar()
+        .await
+        .unwrap();
+    test_context
+        .context
+        .warp_to_slot(clock.slot + pyth::pc::MAX_COMPONENT_STALENESS + 1)
+        .unwrap();
+
+    for (publisher, price) in publishers[1..].iter().zip([100, 102].iter()) {
+        test_context
+            .process(
+                &[submit_price_instruction(oracle, publisher, *price, 0)],
+                &[publisher],
+            )
+            .await
+            .unwrap();
+    }
+
+    // two fresh publishers leave an even number of values, the median averages the middle two
+    let account = test_context.get_account(oracle).await;
+    let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+    assert_eq!(price.comp[0].agg.price, 1_000);
+    assert_eq!(price.agg.price, 101);
+    assert_eq!(price.agg.conf, 1);
+}
+
+#[tokio::test]
+async fn pyth_rejects_uninitialized_price_account() {
+    let mut test_context = setup().await;
+    let price = test_context
+        .create_pyth_account(std::mem::size_of::<pyth::pc::Price>())
+        .await;
+
+    let result = test_context
+        .process(
+            &[pyth_instruction(
+                price,
+                pyth::instruction::SetPrice {
+                    price: 10_i64.pow(7),
+                }
+                .data(),
+            )],
+            &[],
+        )
+        .await;
+    assert_custom_error(result, pyth::ErrorCode::InvalidMagic);
+}
+
+#[tokio::test]
+async fn pyth_replays_price_path() {
+    let mut test_context = setup().await;
+    let oracle = test_context.oracle;
+    let price_path = test_context
+        .create_pyth_account(8 + std::mem::size_of::<pyth::price_path::PricePath>())
+        .await;
+
+    let csv = "slot,price,conf\n100,1.0,0.01\n4600,2.0,0.02\n9100,3,0\n";
+    let points = pyth::replay::read_price_points_csv(csv.as_bytes(), -7).unwrap();
+    assert_eq!(
+        points[1],
+        PricePoint {
+            slot: 4600,
+            price: 2 * 10_i64.pow(7),
+            conf: 2 * 10_u64.pow(5),
+        }
+    );
+
+    let mut instructions = vec![Instruction {
+        program_id: pyth::ID,
+        accounts: pyth::accounts::InitializePricePath {
+            price_path,
+            price: oracle,
+        }
+        .to_account_metas(None),
+        data: pyth::instruction::InitializePricePath {}.data(),
+    }];
+    instructions.extend(pyth::replay::append_price_points_instructions(
+        price_path, &points,
+    ));
+    test_context.process(&instructions, &[]).await.unwrap();
+
+    let advance = Instruction {
+        program_id: pyth::ID,
+        accounts: pyth::accounts::AdvancePricePath {
+            price_path,
+            price: oracle,
+        }
+        .to_account_metas(None),
+        data: pyth::instruction::AdvancePricePath {}.data(),
+    };
+    // the twap follows the recorded slots, warping only gives each advance a new blockhash
+    let expected_twaps = [10_i64.pow(7), 10_i64.pow(7), 15 * 10_i64.pow(6)];
+    let mut slot = 1_000;
+    for (point, expected_twap) in points.iter().zip(expected_twaps.iter()) {
+        test_context.context.warp_to_slot(slot).unwrap();
+        slot += 1_000;
+        test_context.process(&[advance.clone()], &[]).await.unwrap();
+        let account = test_context.get_account(oracle).await;
+        let price: pyth::pc::Price = bytemuck::pod_read_unaligned(&account.data);
+        assert_eq!(price.agg.price, point.price);
+        assert_eq!(price.agg.conf, point.conf);
+        assert_eq!(price.twap, *expected_twap);
+    }
+
+    test_context.context.warp_to_slot(slot).unwrap();
+    let result = test_context.process(&[advance], &[]).await;
+    assert_custom_error(result, pyth::ErrorCode::PricePathFinished);
+}
+
+fn submit_price_instruction(
+    price: Pubkey,
+    publisher: &Keypair,
+    submitted_price: i64,
+    conf: u64,
+) -> Instruction {
+    Instruction {
+        program_id: pyth::ID,
+        accounts: pyth::accounts::SubmitPrice {
+            price,
+            publisher: publisher.pubkey(),
+        }
+        .to_account_metas(None),
+        data: pyth::instruction::SubmitPrice {
+            price: submitted_price,
+            conf,
+        }
+        .data(),
+    }
+}
+
+fn pyth_instruction(price: Pubkey, data: Vec<u8>) -> Instruction {
+    Instruction {
+        program_id: pyth::ID,
+        accounts: pyth::accounts::SetPrice { price }.to_account_metas(None),
+        data,
+    }
+}
+
+fn assert_custom_error(result: Result<(), TransportError>, error: impl Into<ProgramError>) {
+    let expected = match error.into() {
+        ProgramError::Custom(code) => code,
+        error => panic!("{:?} is not a custom error", error),
+    };
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected),
+        result => panic!("expected custom error {}, got {:?}", expected, result),
+    }
+}
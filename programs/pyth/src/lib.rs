@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
-mod pc;
-use pc::Price;
+pub mod pc;
+pub mod price_path;
+#[cfg(not(target_arch = "bpf"))]
+pub mod replay;
+use pc::{AccountType, Mapping, Price, PriceStatus, Product, ProductAttribute};
+use price_path::{PricePath, PricePoint};
 
 #[cfg(feature = "mainnet-beta")]
 declare_id!("GWXu4vLvXFN87dePFvM7Ejt8HEALEG9GNmwimNKHZrXG");
@@ -11,18 +15,22 @@ declare_id!("ASfdvRMCan2aoWtbDi5HLXhz2CFfgEkuDoxc57bJLKLX");
 pub mod pyth {
     use super::*;
 
-    pub fn initialize(
-        ctx: Context<Initialize>,
-        price: i64,
-        expo: i32,
-        _conf: u64,
-    ) -> ProgramResult {
+    pub fn initialize(ctx: Context<Initialize>, price: i64, expo: i32, conf: u64) -> ProgramResult {
         let oracle = &ctx.accounts.price;
+        let slot = Clock::get()?.slot;
 
-        let mut price_oracle = Price::load(&oracle).unwrap();
+        let mut price_oracle = Price::load_uninitialized(&oracle)?;
 
+        price_oracle.magic = pc::MAGIC;
+        price_oracle.ver = pc::VERSION_2;
+        price_oracle.atype = AccountType::Price as u32;
+        price_oracle.size = Price::used_size(0);
         price_oracle.agg.price = price;
-        price_oracle.agg.conf = 0;
+        price_oracle.agg.conf = conf;
+        price_oracle.agg.status = PriceStatus::Trading;
+        price_oracle.agg.pub_slot = slot;
+        price_oracle.valid_slot = slot;
+        price_oracle.curr_slot = slot;
 
         price_oracle.twap = price;
         price_oracle.expo = expo;
@@ -32,15 +40,149 @@ pub mod pyth {
 
     pub fn set_price(ctx: Context<SetPrice>, price: i64) -> ProgramResult {
         let oracle = &ctx.accounts.price;
-        let mut price_oracle = Price::load(&oracle).unwrap();
-
-        price_oracle.twap = price_oracle
-            .twap
-            .checked_add(price)
-            .unwrap()
-            .checked_div(2)
-            .unwrap(); //todo
+        let slot = Clock::get()?.slot;
+        let mut price_oracle = Price::load(&oracle)?;
+
+        price_oracle.update_twap(slot);
         price_oracle.agg.price = price as i64;
+        price_oracle.agg.pub_slot = slot;
+        price_oracle.valid_slot = slot;
+        Ok(())
+    }
+
+    /// Sets the twap and restarts its accumulation from the current slot
+    pub fn set_twap(ctx: Context<SetPrice>, twap: i64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let slot = Clock::get()?.slot;
+        let mut price_oracle = Price::load(&oracle)?;
+
+        price_oracle.twap = twap;
+        price_oracle.curr_slot = slot;
+        Ok(())
+    }
+
+    pub fn set_conf(ctx: Context<SetPrice>, conf: u64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(&oracle)?;
+
+        price_oracle.agg.conf = conf;
+        Ok(())
+    }
+
+    pub fn set_status(ctx: Context<SetPrice>, status: PriceStatus) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(&oracle)?;
+
+        price_oracle.agg.status = status;
+        Ok(())
+    }
+
+    /// Overrides the slots stamped by set_price, e.g. to make the price look stale
+    pub fn set_slots(ctx: Context<SetPrice>, pub_slot: u64, valid_slot: u64) -> ProgramResult {
+        let oracle = &ctx.accounts.price;
+        let mut price_oracle = Price::load(&oracle)?;
+
+        price_oracle.agg.pub_slot = pub_slot;
+        price_oracle.valid_slot = valid_slot;
+        Ok(())
+    }
+
+    pub fn add_publisher(ctx: Context<AddPublisher>, publisher: Pubkey) -> ProgramResult {
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+
+        price_oracle.add_publisher(publisher)
+    }
+
+    /// Records the publisher's component price and recomputes the aggregate
+    pub fn submit_price(ctx: Context<SubmitPrice>, price: i64, conf: u64) -> ProgramResult {
+        let publisher = ctx.accounts.publisher.key();
+        let slot = Clock::get()?.slot;
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+
+        price_oracle.submit_component(publisher, price, conf, slot)?;
+        price_oracle.aggregate(publisher, slot);
+        Ok(())
+    }
+
+    pub fn initialize_price_path(ctx: Context<InitializePricePath>) -> ProgramResult {
+        let mut price_path = ctx.accounts.price_path.load_init()?;
+
+        price_path.price = ctx.accounts.price.key();
+        Ok(())
+    }
+
+    pub fn append_price_points(
+        ctx: Context<AppendPricePoints>,
+        points: Vec<PricePoint>,
+    ) -> ProgramResult {
+        let mut price_path = ctx.accounts.price_path.load_mut()?;
+
+        price_path.append(&points)
+    }
+
+    /// Moves the price account to the next point of the path. The twap is weighted by the
+    /// recorded slots between points while the publish slots follow the clock
+    pub fn advance_price_path(ctx: Context<AdvancePricePath>) -> ProgramResult {
+        let slot = Clock::get()?.slot;
+        let mut price_path = ctx.accounts.price_path.load_mut()?;
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+
+        let (point, elapsed_slots) = price_path.advance()?;
+        price_oracle.accumulate_twap(elapsed_slots);
+        price_oracle.agg.price = point.price;
+        price_oracle.agg.conf = point.conf;
+        price_oracle.agg.pub_slot = slot;
+        price_oracle.valid_slot = slot;
+        price_oracle.curr_slot = slot;
+        Ok(())
+    }
+
+    pub fn initialize_mapping(ctx: Context<InitializeMapping>) -> ProgramResult {
+        let mut mapping = Mapping::load_uninitialized(&ctx.accounts.mapping)?;
+
+        mapping.magic = pc::MAGIC;
+        mapping.ver = pc::VERSION_2;
+        mapping.atype = AccountType::Mapping as u32;
+        mapping.size = pc::MAPPING_HDR_SIZE as u32;
+        Ok(())
+    }
+
+    pub fn initialize_product(
+        ctx: Context<InitializeProduct>,
+        attributes: Vec<ProductAttribute>,
+    ) -> ProgramResult {
+        let mut product = Product::load_uninitialized(&ctx.accounts.product)?;
+
+        product.magic = pc::MAGIC;
+        product.ver = pc::VERSION_2;
+        product.atype = AccountType::Product as u32;
+        product.set_attributes(&attributes)?;
+        Ok(())
+    }
+
+    /// Appends the product to the mapping's product list
+    pub fn add_product(ctx: Context<AddProduct>) -> ProgramResult {
+        let mut mapping = Mapping::load(&ctx.accounts.mapping)?;
+
+        mapping.add_product(ctx.accounts.product.key())
+    }
+
+    /// Pushes the price to the front of the product's linked list of price accounts
+    pub fn add_price(ctx: Context<AddPrice>) -> ProgramResult {
+        let mut product = Product::load(&ctx.accounts.product)?;
+        let mut price_oracle = Price::load(&ctx.accounts.price)?;
+
+        price_oracle.next = product.px_acc;
+        price_oracle.prod = ctx.accounts.product.key().into();
+        product.px_acc = ctx.accounts.price.key().into();
+        Ok(())
+    }
+
+    /// Links a mapping to the next one once its product list is full
+    pub fn set_next_mapping(ctx: Context<SetNextMapping>) -> ProgramResult {
+        let mut mapping = Mapping::load(&ctx.accounts.mapping)?;
+
+        mapping.next = ctx.accounts.next_mapping.key().into();
         Ok(())
     }
 }
@@ -56,3 +198,108 @@ pub struct Initialize<'info> {
     #[account(mut)]
     pub price: AccountInfo<'info>,
 }
+
+#[derive(Accounts)]
+pub struct AddPublisher<'info> {
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitPrice<'info> {
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+    pub publisher: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePricePath<'info> {
+    #[account(zero)]
+    pub price_path: AccountLoader<'info, PricePath>,
+    pub price: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AppendPricePoints<'info> {
+    #[account(mut)]
+    pub price_path: AccountLoader<'info, PricePath>,
+}
+
+#[derive(Accounts)]
+pub struct AdvancePricePath<'info> {
+    #[account(
+        mut,
+        constraint = price_path.load()?.price.eq(&price.key()) @ ErrorCode::InvalidPricePathPrice
+    )]
+    pub price_path: AccountLoader<'info, PricePath>,
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMapping<'info> {
+    #[account(mut)]
+    pub mapping: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProduct<'info> {
+    #[account(mut)]
+    pub product: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddProduct<'info> {
+    #[account(mut)]
+    pub mapping: AccountInfo<'info>,
+    pub product: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddPrice<'info> {
+    #[account(mut)]
+    pub product: AccountInfo<'info>,
+    #[account(mut)]
+    pub price: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetNextMapping<'info> {
+    #[account(mut)]
+    pub mapping: AccountInfo<'info>,
+    pub next_mapping: AccountInfo<'info>,
+}
+
+#[error]
+pub enum ErrorCode {
+    #[msg("Mapping account has no room for another product")]
+    MappingFull,
+    #[msg("Product attributes do not fit in the product account")]
+    ProductAttributesTooLarge,
+    #[msg("Price account has no room for another publisher")]
+    PriceComponentsFull,
+    #[msg("Publisher is already a component of the price account")]
+    PublisherAlreadyAdded,
+    #[msg("Publisher is not a component of the price account")]
+    UnknownPublisher,
+    #[msg("Account is not owned by the pyth program")]
+    InvalidAccountOwner,
+    #[msg("Account size does not match the pyth account layout")]
+    InvalidAccountSize,
+    #[msg("Account does not start with the pyth magic number")]
+    InvalidMagic,
+    #[msg("Account version is not supported")]
+    InvalidVersion,
+    #[msg("Account type does not match")]
+    InvalidAccountType,
+    #[msg("Price path has no room for more points")]
+    PricePathFull,
+    #[msg("Price path points must have increasing slots")]
+    PricePathSlotsNotIncreasing,
+    #[msg("Price path has no more points")]
+    PricePathFinished,
+    #[msg("Price path drives a different price account")]
+    InvalidPricePathPrice,
+    #[msg("Account data is not aligned for the pyth account layout")]
+    InvalidAccountAlignment,
+}
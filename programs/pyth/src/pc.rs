@@ -1,7 +1,17 @@
 use crate::*;
 use anchor_lang::prelude::AccountInfo;
-use bytemuck::{cast_slice_mut, from_bytes_mut, try_cast_slice_mut, Pod, Zeroable};
-use std::cell::RefMut;
+use bytemuck::{
+    from_bytes, from_bytes_mut, try_from_bytes, try_from_bytes_mut, Pod, PodCastError, Zeroable,
+};
+use std::cell::{Ref, RefMut};
+
+pub const MAGIC: u32 = 0xa1b2c3d4;
+pub const VERSION_2: u32 = 2;
+pub const MAP_TABLE_SIZE: usize = 640;
+pub const MAPPING_HDR_SIZE: usize = 56;
+pub const PROD_ACCT_SIZE: usize = 512;
+pub const PROD_HDR_SIZE: usize = 48;
+pub const PROD_ATTR_SIZE: usize = PROD_ACCT_SIZE - PROD_HDR_SIZE;
 
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
@@ -9,9 +19,25 @@ pub struct AccKey {
     pub val: [u8; 32],
 }
 
-#[derive(Copy, Clone)]
+impl From<Pubkey> for AccKey {
+    fn from(key: Pubkey) -> Self {
+        AccKey {
+            val: key.to_bytes(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub enum AccountType {
+    Unknown,
+    Mapping,
+    Product,
+    Price,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Copy, Clone, PartialEq, Debug)]
 #[repr(C)]
-#[allow(dead_code)]
 pub enum PriceStatus {
     Unknown,
     Trading,
@@ -49,9 +75,9 @@ pub struct PriceInfo {
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
 pub struct PriceComp {
-    publisher: AccKey,
-    agg: PriceInfo,
-    latest: PriceInfo,
+    pub publisher: AccKey,
+    pub agg: PriceInfo,
+    pub latest: PriceInfo,
 }
 
 #[derive(Copy, Clone)]
@@ -98,17 +124,150 @@ pub struct Price {
     pub comp: [PriceComp; 32], // Price components one per quoter.
 }
 
+/// Size of the price account header before the component prices
+pub const PRICE_HDR_SIZE: usize = 240;
+
+/// Slots the twap averages over, roughly an hour of 400ms slots
+pub const TWAP_PERIOD: u64 = 9_000;
+
+/// Slots after which a publisher's component price is left out of the aggregate, like the pyth
+/// aggregator's max send latency
+pub const MAX_COMPONENT_STALENESS: u64 = 25;
+
 impl Price {
+    /// Header plus the num component prices in use, like the size the pyth program records
+    pub fn used_size(num: u32) -> u32 {
+        (PRICE_HDR_SIZE + num as usize * std::mem::size_of::<PriceComp>()) as u32
+    }
+
+    /// Folds the aggregate price held since curr_slot into the twap, then moves curr_slot to slot
+    pub fn update_twap(&mut self, slot: u64) {
+        self.accumulate_twap(slot.saturating_sub(self.curr_slot));
+        self.curr_slot = slot;
+    }
+
+    /// Folds the aggregate price held for elapsed_slots into the twap as an exponential moving
+    /// average weighted by the slots elapsed
+    pub fn accumulate_twap(&mut self, elapsed_slots: u64) {
+        let elapsed = elapsed_slots.min(TWAP_PERIOD) as i128;
+        let twap = (self.twap as i128)
+            .checked_mul(TWAP_PERIOD as i128 - elapsed)
+            .unwrap()
+            .checked_add((self.agg.price as i128).checked_mul(elapsed).unwrap())
+            .unwrap()
+            .checked_div(TWAP_PERIOD as i128)
+            .unwrap();
+
+        self.twap = twap as i64;
+    }
+
+    pub fn add_publisher(&mut self, publisher: Pubkey) -> ProgramResult {
+        let num = self.num as usize;
+        if self.comp[..num]
+            .iter()
+            .any(|comp| comp.publisher.val == publisher.to_bytes())
+        {
+            return Err(ErrorCode::PublisherAlreadyAdded.into());
+        }
+
+        if num >= self.comp.len() {
+            return Err(ErrorCode::PriceComponentsFull.into());
+        }
+
+        self.comp[num].publisher = publisher.into();
+        self.num += 1;
+        self.size = Price::used_size(self.num);
+        Ok(())
+    }
+
+    pub fn submit_component(
+        &mut self,
+        publisher: Pubkey,
+        price: i64,
+        conf: u64,
+        slot: u64,
+    ) -> ProgramResult {
+        let num = self.num as usize;
+        let comp = self.comp[..num]
+            .iter_mut()
+            .find(|comp| comp.publisher.val == publisher.to_bytes())
+            .ok_or(ErrorCode::UnknownPublisher)?;
+
+        comp.latest = PriceInfo {
+            price,
+            conf,
+            status: PriceStatus::Trading,
+            corp_act: CorpAction::NoCorpAct,
+            pub_slot: slot,
+        };
+        comp.agg = comp.latest;
+        Ok(())
+    }
+
+    /// Recomputes agg from the trading components published within the last
+    /// MAX_COMPONENT_STALENESS slots like the pyth aggregator: each component contributes
+    /// price - conf, price and price + conf, agg.price is the median of those values and agg.conf
+    /// the larger distance from the median to the 25th and 75th percentiles
+    pub fn aggregate(&mut self, publisher: Pubkey, slot: u64) {
+        let num = self.num as usize;
+        let mut values: Vec<i64> = self.comp[..num]
+            .iter()
+            .filter(|comp| {
+                comp.agg.status == PriceStatus::Trading
+                    && slot.saturating_sub(comp.agg.pub_slot) <= MAX_COMPONENT_STALENESS
+            })
+            .flat_map(|comp| {
+                let conf = comp.agg.conf as i64;
+                vec![
+                    comp.agg.price.saturating_sub(conf),
+                    comp.agg.price,
+                    comp.agg.price.saturating_add(conf),
+                ]
+            })
+            .collect();
+        if values.is_empty() {
+            return;
+        }
+        values.sort_unstable();
+
+        let percentile = |quarters: usize| values[(values.len() - 1) * quarters / 4];
+        let middle = values.len() / 2;
+        let median = if values.len() % 2 == 0 {
+            ((values[middle - 1] as i128 + values[middle] as i128) / 2) as i64
+        } else {
+            values[middle]
+        };
+        let conf = (median - percentile(1)).max(percentile(3) - median);
+
+        self.update_twap(slot);
+        self.agg.price = median;
+        self.agg.conf = conf as u64;
+        self.agg.status = PriceStatus::Trading;
+        self.agg.pub_slot = slot;
+        self.valid_slot = slot;
+        self.agg_pub = publisher.into();
+    }
+
+    /// Loads an account that has not been initialized yet, checking only its owner and size
     #[inline]
-    pub fn load<'a>(price_feed: &'a AccountInfo) -> Result<RefMut<'a, Price>, ProgramError> {
-        let account_data: RefMut<'a, [u8]>;
-        let state: RefMut<'a, Self>;
+    pub fn load_uninitialized<'a>(
+        price_feed: &'a AccountInfo,
+    ) -> Result<RefMut<'a, Price>, ProgramError> {
+        load_account_mut(price_feed)
+    }
 
-        account_data = RefMut::map(price_feed.try_borrow_mut_data().unwrap(), |data| *data);
+    #[inline]
+    pub fn load<'a>(price_feed: &'a AccountInfo) -> Result<RefMut<'a, Price>, ProgramError> {
+        let state: RefMut<'a, Self> = load_account_mut(price_feed)?;
+        check_header(state.magic, state.ver, state.atype, AccountType::Price)?;
+        Ok(state)
+    }
 
-        state = RefMut::map(account_data, |data| {
-            from_bytes_mut(cast_slice_mut::<u8, u8>(try_cast_slice_mut(data).unwrap()))
-        });
+    /// Read-only load for programs that only read the account, e.g. to get the oracle price
+    #[inline]
+    pub fn load_ref<'a>(price_feed: &'a AccountInfo) -> Result<Ref<'a, Price>, ProgramError> {
+        let state: Ref<'a, Self> = load_account(price_feed)?;
+        check_header(state.magic, state.ver, state.atype, AccountType::Price)?;
         Ok(state)
     }
 }
@@ -118,3 +277,187 @@ unsafe impl Zeroable for Price {}
 
 #[cfg(target_endian = "little")]
 unsafe impl Pod for Price {}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Mapping {
+    pub magic: u32,                         // Pyth magic number.
+    pub ver: u32,                           // Program version.
+    pub atype: u32,                         // Account type.
+    pub size: u32,                          // Account used size.
+    pub num: u32,                           // Number of product accounts.
+    pub unused: u32,                        // Unused.
+    pub next: AccKey,                       // Next mapping account in linked list.
+    pub products: [AccKey; MAP_TABLE_SIZE], // Product account keys.
+}
+
+impl Mapping {
+    /// Loads an account that has not been initialized yet, checking only its owner and size
+    #[inline]
+    pub fn load_uninitialized<'a>(
+        mapping: &'a AccountInfo,
+    ) -> Result<RefMut<'a, Mapping>, ProgramError> {
+        load_account_mut(mapping)
+    }
+
+    #[inline]
+    pub fn load<'a>(mapping: &'a AccountInfo) -> Result<RefMut<'a, Mapping>, ProgramError> {
+        let state: RefMut<'a, Self> = load_account_mut(mapping)?;
+        check_header(state.magic, state.ver, state.atype, AccountType::Mapping)?;
+        Ok(state)
+    }
+
+    pub fn add_product(&mut self, product: Pubkey) -> ProgramResult {
+        let num = self.num as usize;
+        if num >= MAP_TABLE_SIZE {
+            return Err(ErrorCode::MappingFull.into());
+        }
+
+        self.products[num] = product.into();
+        self.num += 1;
+        self.size = (MAPPING_HDR_SIZE + self.num as usize * std::mem::size_of::<AccKey>()) as u32;
+        Ok(())
+    }
+}
+
+#[cfg(target_endian = "little")]
+unsafe impl Zeroable for Mapping {}
+
+#[cfg(target_endian = "little")]
+unsafe impl Pod for Mapping {}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct ProductAttribute {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Product {
+    pub magic: u32,                 // Pyth magic number.
+    pub ver: u32,                   // Program version.
+    pub atype: u32,                 // Account type.
+    pub size: u32,                  // Account used size.
+    pub px_acc: AccKey,             // First price account in linked list.
+    pub attr: [u8; PROD_ATTR_SIZE], // Key/value pairs of reference attributes.
+}
+
+impl Product {
+    /// Loads an account that has not been initialized yet, checking only its owner and size
+    #[inline]
+    pub fn load_uninitialized<'a>(
+        product: &'a AccountInfo,
+    ) -> Result<RefMut<'a, Product>, ProgramError> {
+        load_account_mut(product)
+    }
+
+    #[inline]
+    pub fn load<'a>(product: &'a AccountInfo) -> Result<RefMut<'a, Product>, ProgramError> {
+        let state: RefMut<'a, Self> = load_account_mut(product)?;
+        check_header(state.magic, state.ver, state.atype, AccountType::Product)?;
+        Ok(state)
+    }
+
+    /// Writes the attributes as length-prefixed key and value strings, like the pyth program
+    pub fn set_attributes(&mut self, attributes: &[ProductAttribute]) -> ProgramResult {
+        let mut attr = [0_u8; PROD_ATTR_SIZE];
+        let mut offset = 0;
+        for attribute in attributes.iter() {
+            for string in [&attribute.key, &attribute.value].iter() {
+                let bytes = string.as_bytes();
+                if bytes.len() > u8::MAX as usize || offset + 1 + bytes.len() > PROD_ATTR_SIZE {
+                    return Err(ErrorCode::ProductAttributesTooLarge.into());
+                }
+
+                attr[offset] = bytes.len() as u8;
+                attr[offset + 1..offset + 1 + bytes.len()].copy_from_slice(bytes);
+                offset += 1 + bytes.len();
+            }
+        }
+
+        self.attr = attr;
+        self.size = (PROD_HDR_SIZE + offset) as u32;
+        Ok(())
+    }
+
+    pub fn attributes(&self) -> Vec<ProductAttribute> {
+        let end = (self.size as usize)
+            .saturating_sub(PROD_HDR_SIZE)
+            .min(PROD_ATTR_SIZE);
+        let mut attributes = vec![];
+        let mut offset = 0;
+        while offset < end {
+            let key = read_attribute_string(&self.attr[..end], &mut offset);
+            let value = read_attribute_string(&self.attr[..end], &mut offset);
+            attributes.push(ProductAttribute { key, value });
+        }
+        attributes
+    }
+}
+
+fn read_attribute_string(attr: &[u8], offset: &mut usize) -> String {
+    let len = attr.get(*offset).copied().unwrap_or(0) as usize;
+    let start = (*offset + 1).min(attr.len());
+    let end = (start + len).min(attr.len());
+    *offset = start + len;
+    String::from_utf8_lossy(&attr[start..end]).into_owned()
+}
+
+#[cfg(target_endian = "little")]
+unsafe impl Zeroable for Product {}
+
+#[cfg(target_endian = "little")]
+unsafe impl Pod for Product {}
+
+fn check_account<T>(account: &AccountInfo) -> ProgramResult {
+    if account.owner != &crate::ID {
+        return Err(ErrorCode::InvalidAccountOwner.into());
+    }
+
+    if account.data_len() != std::mem::size_of::<T>() {
+        return Err(ErrorCode::InvalidAccountSize.into());
+    }
+
+    Ok(())
+}
+
+fn check_header(magic: u32, ver: u32, atype: u32, account_type: AccountType) -> ProgramResult {
+    if magic != MAGIC {
+        return Err(ErrorCode::InvalidMagic.into());
+    }
+
+    if ver != VERSION_2 {
+        return Err(ErrorCode::InvalidVersion.into());
+    }
+
+    if atype != account_type as u32 {
+        return Err(ErrorCode::InvalidAccountType.into());
+    }
+
+    Ok(())
+}
+
+fn pod_cast_error(error: PodCastError) -> ProgramError {
+    match error {
+        PodCastError::SizeMismatch => ErrorCode::InvalidAccountSize.into(),
+        _ => ErrorCode::InvalidAccountAlignment.into(),
+    }
+}
+
+// the casts are checked before mapping the borrows so from_bytes(_mut) can't panic
+fn load_account_mut<'a, T: Pod>(account: &'a AccountInfo) -> Result<RefMut<'a, T>, ProgramError> {
+    check_account::<T>(account)?;
+    let mut account_data = account.try_borrow_mut_data()?;
+    try_from_bytes_mut::<T>(&mut account_data[..]).map_err(pod_cast_error)?;
+    Ok(RefMut::map(account_data, |data| {
+        from_bytes_mut(&mut data[..])
+    }))
+}
+
+fn load_account<'a, T: Pod>(account: &'a AccountInfo) -> Result<Ref<'a, T>, ProgramError> {
+    check_account::<T>(account)?;
+    let account_data = account.try_borrow_data()?;
+    try_from_bytes::<T>(&account_data[..]).map_err(pod_cast_error)?;
+    Ok(Ref::map(account_data, |data| from_bytes(&data[..])))
+}
@@ -0,0 +1,66 @@
+use crate::*;
+use bytemuck::{Pod, Zeroable};
+
+pub const PRICE_PATH_CAPACITY: usize = 1024;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct PricePoint {
+    pub slot: u64,
+    pub price: i64,
+    pub conf: u64,
+}
+
+#[cfg(target_endian = "little")]
+unsafe impl Zeroable for PricePoint {}
+
+#[cfg(target_endian = "little")]
+unsafe impl Pod for PricePoint {}
+
+/// Recorded (slot, price, conf) points replayed into a price account one at a time
+#[account(zero_copy)]
+pub struct PricePath {
+    pub price: Pubkey,
+    pub len: u64,
+    /// Index of the point advance_price_path applies next
+    pub next: u64,
+    pub points: [PricePoint; 1024],
+}
+
+impl PricePath {
+    pub fn append(&mut self, points: &[PricePoint]) -> ProgramResult {
+        for point in points.iter() {
+            let len = self.len as usize;
+            if len >= PRICE_PATH_CAPACITY {
+                return Err(ErrorCode::PricePathFull.into());
+            }
+
+            if len > 0 && point.slot <= self.points[len - 1].slot {
+                return Err(ErrorCode::PricePathSlotsNotIncreasing.into());
+            }
+
+            self.points[len] = *point;
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Next point and the recorded slots elapsed since the previous one
+    pub fn advance(&mut self) -> Result<(PricePoint, u64), ProgramError> {
+        let next = self.next as usize;
+        if next >= self.len as usize {
+            return Err(ErrorCode::PricePathFinished.into());
+        }
+
+        let point = self.points[next];
+        let elapsed_slots = if next == 0 {
+            0
+        } else {
+            point.slot - self.points[next - 1].slot
+        };
+        self.next += 1;
+
+        Ok((point, elapsed_slots))
+    }
+}
@@ -0,0 +1,101 @@
+use crate::price_path::PricePoint;
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use std::io::{self, BufRead};
+
+/// Points per append_price_points instruction, small enough to fit in a transaction
+pub const PRICE_POINTS_PER_INSTRUCTION: usize = 32;
+
+/// Reads `slot,price,conf` rows of recorded market data into price points. Prices and
+/// confidences are decimals scaled to the price account's expo, e.g. 172.45 with expo -7 is
+/// 1724500000. A header row and blank lines are skipped
+pub fn read_price_points_csv<R: BufRead>(reader: R, expo: i32) -> io::Result<Vec<PricePoint>> {
+    let mut points = vec![];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        if line_number == 0 && fields[0].parse::<u64>().is_err() {
+            continue;
+        }
+
+        let invalid_row = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid price path row {}: {}", line_number + 1, line),
+            )
+        };
+        if fields.len() != 3 {
+            return Err(invalid_row());
+        }
+
+        let slot = fields[0].parse::<u64>().map_err(|_| invalid_row())?;
+        let price = parse_decimal(fields[1], expo).ok_or_else(invalid_row)?;
+        let conf = parse_decimal(fields[2], expo)
+            .filter(|conf| *conf >= 0)
+            .ok_or_else(invalid_row)? as u64;
+        points.push(PricePoint { slot, price, conf });
+    }
+
+    Ok(points)
+}
+
+/// Instructions appending the points to the price path, in order
+pub fn append_price_points_instructions(
+    price_path: Pubkey,
+    points: &[PricePoint],
+) -> Vec<Instruction> {
+    points
+        .chunks(PRICE_POINTS_PER_INSTRUCTION)
+        .map(|chunk| Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::AppendPricePoints { price_path }.to_account_metas(None),
+            data: crate::instruction::AppendPricePoints {
+                points: chunk.to_vec(),
+            }
+            .data(),
+        })
+        .collect()
+}
+
+/// Scales a decimal string by 10^-expo, truncating extra fractional digits
+fn parse_decimal(value: &str, expo: i32) -> Option<i64> {
+    if expo > 0 {
+        return None;
+    }
+
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let mut parts = value.splitn(2, '.');
+    let integer = parts.next()?;
+    let fraction = parts.next().unwrap_or("");
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let decimals = expo.unsigned_abs() as usize;
+    let mut digits: String = integer.to_string();
+    digits.extend(
+        fraction
+            .chars()
+            .chain(std::iter::repeat('0'))
+            .take(decimals),
+    );
+    if !integer
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let scaled = digits.parse::<i64>().ok()?;
+    Some(if negative { -scaled } else { scaled })
+}
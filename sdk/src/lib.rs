@@ -0,0 +1,884 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::{system_program, sysvar};
+use anchor_lang::{AccountDeserialize, InstructionData};
+use clearing_house::state::state::State;
+use drift_client::state::{
+    Config, MarketRiskLimit, OracleGuards, OrderBook, RiskLimits, TriggerOrder, TwapOrder,
+    VaultDepositor, WithdrawalRequest,
+};
+use drift_client::{ManagePositionOptionalAccounts, PositionDirection, RebalanceLeg};
+
+pub mod pda;
+
+/// Clearing house accounts every drift_client instruction forwards, read from its State account
+#[derive(Clone, Copy, Debug)]
+pub struct ClearingHouseAccounts {
+    pub program_id: Pubkey,
+    pub state: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub collateral_vault: Pubkey,
+    pub collateral_vault_authority: Pubkey,
+    pub insurance_vault: Pubkey,
+    pub insurance_vault_authority: Pubkey,
+    pub markets: Pubkey,
+    pub deposit_history: Pubkey,
+    pub funding_payment_history: Pubkey,
+    pub funding_rate_history: Pubkey,
+    pub trade_history: Pubkey,
+}
+
+impl ClearingHouseAccounts {
+    pub fn new(program_id: Pubkey, state_key: Pubkey, state: &State) -> Self {
+        ClearingHouseAccounts {
+            program_id,
+            state: state_key,
+            collateral_mint: state.collateral_mint,
+            collateral_vault: state.collateral_vault,
+            collateral_vault_authority: state.collateral_vault_authority,
+            insurance_vault: state.insurance_vault,
+            insurance_vault_authority: state.insurance_vault_authority,
+            markets: state.markets,
+            deposit_history: state.deposit_history,
+            funding_payment_history: state.funding_payment_history,
+            funding_rate_history: state.funding_rate_history,
+            trade_history: state.trade_history,
+        }
+    }
+}
+
+/// Builds drift_client instructions for the config keyed by owner and sub_account_id
+pub struct DriftClient {
+    pub program_id: Pubkey,
+    pub clearing_house: ClearingHouseAccounts,
+    pub owner: Pubkey,
+    pub sub_account_id: u16,
+}
+
+impl DriftClient {
+    pub fn new(clearing_house: ClearingHouseAccounts, owner: Pubkey, sub_account_id: u16) -> Self {
+        DriftClient {
+            program_id: drift_client::ID,
+            clearing_house,
+            owner,
+            sub_account_id,
+        }
+    }
+
+    pub fn config_address(&self) -> Pubkey {
+        pda::get_config_address(&self.program_id, &self.owner, self.sub_account_id).0
+    }
+
+    pub fn collateral_vault_address(&self) -> Pubkey {
+        pda::get_collateral_vault_address(&self.program_id, &self.config_address()).0
+    }
+
+    pub fn authority_address(&self) -> Pubkey {
+        pda::get_authority_address(
+            &self.program_id,
+            &self.clearing_house.program_id,
+            &self.config_address(),
+        )
+        .0
+    }
+
+    pub fn risk_limits_address(&self) -> Pubkey {
+        pda::get_risk_limits_address(&self.program_id, &self.config_address()).0
+    }
+
+    pub fn vault_depositor_address(&self, depositor: &Pubkey) -> Pubkey {
+        pda::get_vault_depositor_address(&self.program_id, &self.config_address(), depositor).0
+    }
+
+    pub fn trigger_order_address(&self, market_index: u64) -> Pubkey {
+        pda::get_trigger_order_address(&self.program_id, &self.config_address(), market_index).0
+    }
+
+    pub fn order_book_address(&self) -> Pubkey {
+        pda::get_order_book_address(&self.program_id, &self.config_address()).0
+    }
+
+    pub fn twap_order_address(&self, market_index: u64) -> Pubkey {
+        pda::get_twap_order_address(&self.program_id, &self.config_address(), market_index).0
+    }
+
+    pub fn withdrawal_request_address(&self) -> Pubkey {
+        pda::get_withdrawal_request_address(&self.program_id, &self.config_address()).0
+    }
+
+    pub fn clearing_house_user_address(&self) -> Pubkey {
+        pda::get_clearing_house_user_address(
+            &self.clearing_house.program_id,
+            &self.authority_address(),
+        )
+        .0
+    }
+
+    /// withdrawal_destination seeds the withdrawal destinations, the default pubkey leaves them
+    /// empty so nothing can be withdrawn until one is accepted
+    pub fn initialize(&self, admin: Pubkey, withdrawal_destination: Pubkey) -> Instruction {
+        let (config, config_nonce) =
+            pda::get_config_address(&self.program_id, &self.owner, self.sub_account_id);
+        let (collateral_vault, collateral_vault_nonce) =
+            pda::get_collateral_vault_address(&self.program_id, &config);
+        let (risk_limits, risk_limits_nonce) =
+            pda::get_risk_limits_address(&self.program_id, &config);
+        let accounts = drift_client::accounts::Initialize {
+            admin,
+            config,
+            risk_limits,
+            collateral_mint: self.clearing_house.collateral_mint,
+            collateral_vault,
+            authority: self.authority_address(),
+            clearing_house_state: self.clearing_house.state,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+            token_program: anchor_spl::token::ID,
+        };
+        let data = drift_client::instruction::Initialize {
+            _config_nonce: config_nonce,
+            _collateral_vault_nonce: collateral_vault_nonce,
+            sub_account_id: self.sub_account_id,
+            _risk_limits_nonce: risk_limits_nonce,
+            withdrawal_destination,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    /// clearing_house_user_positions is a fresh keypair that must also sign the transaction
+    pub fn initialize_user(
+        &self,
+        admin: Pubkey,
+        clearing_house_user_positions: Pubkey,
+    ) -> Instruction {
+        let authority = self.authority_address();
+        let (clearing_house_user, user_nonce) =
+            pda::get_clearing_house_user_address(&self.clearing_house.program_id, &authority);
+        let accounts = drift_client::accounts::InitializeUser {
+            admin,
+            config: self.config_address(),
+            clearing_house_state: self.clearing_house.state,
+            clearing_house_user,
+            clearing_house_user_positions,
+            authority,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+            clearing_house_program: self.clearing_house.program_id,
+        };
+        let data = drift_client::instruction::InitializeUser {
+            _user_nonce: user_nonce,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn deposit_collateral(
+        &self,
+        config: &Config,
+        admin: Pubkey,
+        admin_collateral_account: Pubkey,
+        amount: u64,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::DepositCollateral {
+            admin,
+            admin_collateral_account,
+            config: self.config_address(),
+            collateral_vault: config.collateral_vault,
+            clearing_house_collateral: self.clearing_house_collateral(config),
+        };
+        let data = drift_client::instruction::DepositCollateral { amount };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn withdraw_collateral(
+        &self,
+        config: &Config,
+        admin: Pubkey,
+        admin_collateral_account: Pubkey,
+        amount: u64,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::WithdrawCollateral {
+            admin,
+            admin_collateral_account,
+            config: self.config_address(),
+            collateral_vault: config.collateral_vault,
+            clearing_house_collateral: self.clearing_house_collateral(config),
+        };
+        let data = drift_client::instruction::WithdrawCollateral { amount };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn request_withdrawal(&self, admin: Pubkey, amount: u64) -> Instruction {
+        let config = self.config_address();
+        let (withdrawal_request, withdrawal_request_nonce) =
+            pda::get_withdrawal_request_address(&self.program_id, &config);
+        let accounts = drift_client::accounts::RequestWithdrawal {
+            admin,
+            config,
+            withdrawal_request,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        };
+        let data = drift_client::instruction::RequestWithdrawal {
+            _withdrawal_request_nonce: withdrawal_request_nonce,
+            amount,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn cancel_withdrawal(&self, admin: Pubkey) -> Instruction {
+        let accounts = drift_client::accounts::CancelWithdrawal {
+            admin,
+            config: self.config_address(),
+            withdrawal_request: self.withdrawal_request_address(),
+        };
+        let data = drift_client::instruction::CancelWithdrawal {};
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn claim_withdrawal(
+        &self,
+        config: &Config,
+        admin: Pubkey,
+        admin_collateral_account: Pubkey,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::ClaimWithdrawal {
+            admin,
+            admin_collateral_account,
+            config: self.config_address(),
+            withdrawal_request: self.withdrawal_request_address(),
+            collateral_vault: config.collateral_vault,
+            clearing_house_collateral: self.clearing_house_collateral(config),
+        };
+        let data = drift_client::instruction::ClaimWithdrawal {};
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        self.instruction(account_metas, data.data())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_position(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        oracle: Pubkey,
+        direction: PositionDirection,
+        quote_asset_amount: u128,
+        market_index: u64,
+        limit_price: u128,
+        discount_token: Option<Pubkey>,
+        referrer: Option<Pubkey>,
+    ) -> Instruction {
+        let mut account_metas = self
+            .open_position_accounts(config, trader, oracle)
+            .to_account_metas(None);
+        let optional_accounts =
+            Self::append_optional_accounts(&mut account_metas, discount_token, referrer);
+        let data = drift_client::instruction::OpenPosition {
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            optional_accounts,
+        };
+        self.instruction(account_metas, data.data())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn reduce_position(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        oracle: Pubkey,
+        direction: PositionDirection,
+        quote_asset_amount: u128,
+        market_index: u64,
+        limit_price: u128,
+        discount_token: Option<Pubkey>,
+        referrer: Option<Pubkey>,
+    ) -> Instruction {
+        let mut account_metas = self
+            .open_position_accounts(config, trader, oracle)
+            .to_account_metas(None);
+        let optional_accounts =
+            Self::append_optional_accounts(&mut account_metas, discount_token, referrer);
+        let data = drift_client::instruction::ReducePosition {
+            direction,
+            quote_asset_amount,
+            market_index,
+            limit_price,
+            optional_accounts,
+        };
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn close_position(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        oracle: Pubkey,
+        market_index: u64,
+        discount_token: Option<Pubkey>,
+        referrer: Option<Pubkey>,
+    ) -> Instruction {
+        let accounts = drift_client::accounts::ClosePosition {
+            trader,
+            config: self.config_address(),
+            clearing_house: self.clearing_house_trade(config),
+            oracle,
+        };
+        let mut account_metas = accounts.to_account_metas(None);
+        let optional_accounts =
+            Self::append_optional_accounts(&mut account_metas, discount_token, referrer);
+        let data = drift_client::instruction::ClosePosition {
+            market_index,
+            optional_accounts,
+        };
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn set_target_position(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        oracle: Pubkey,
+        market_index: u64,
+        target_base_asset_amount: i128,
+        max_slippage: u128,
+    ) -> Instruction {
+        let accounts = drift_client::accounts::SetTargetPosition {
+            trader,
+            config: self.config_address(),
+            risk_limits: config.risk_limits,
+            clearing_house: self.clearing_house_trade(config),
+            oracle,
+        };
+        let data = drift_client::instruction::SetTargetPosition {
+            market_index,
+            target_base_asset_amount,
+            max_slippage,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    /// oracles holds the oracle of each leg's market, in the order of the legs
+    pub fn rebalance(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        legs: Vec<RebalanceLeg>,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::Rebalance {
+            trader,
+            config: self.config_address(),
+            risk_limits: config.risk_limits,
+            clearing_house: self.clearing_house_trade(config),
+        };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        let data = drift_client::instruction::Rebalance { legs };
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn update_delegate(&self, admin: Pubkey, delegate: Pubkey) -> Instruction {
+        let accounts = drift_client::accounts::UpdateDelegate {
+            admin,
+            config: self.config_address(),
+        };
+        let data = drift_client::instruction::UpdateDelegate { delegate };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn propose_admin(&self, admin: Pubkey, pending_admin: Pubkey) -> Instruction {
+        let accounts = drift_client::accounts::ProposeAdmin {
+            admin,
+            config: self.config_address(),
+        };
+        let data = drift_client::instruction::ProposeAdmin { pending_admin };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn accept_admin(&self, pending_admin: Pubkey) -> Instruction {
+        let accounts = drift_client::accounts::AcceptAdmin {
+            pending_admin,
+            config: self.config_address(),
+        };
+        let data = drift_client::instruction::AcceptAdmin {};
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn update_risk_limits(
+        &self,
+        admin: Pubkey,
+        max_leverage: u128,
+        max_oracle_slippage: u128,
+        markets: [MarketRiskLimit; 8],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::UpdateRiskLimits {
+            admin,
+            config: self.config_address(),
+            risk_limits: self.risk_limits_address(),
+        };
+        let data = drift_client::instruction::UpdateRiskLimits {
+            max_leverage,
+            max_oracle_slippage,
+            markets,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn update_oracle_guards(&self, admin: Pubkey, oracle_guards: OracleGuards) -> Instruction {
+        let accounts = drift_client::accounts::UpdateOracleGuards {
+            admin,
+            config: self.config_address(),
+        };
+        let data = drift_client::instruction::UpdateOracleGuards { oracle_guards };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn update_withdrawal_delay(&self, admin: Pubkey, withdrawal_delay: i64) -> Instruction {
+        let accounts = drift_client::accounts::UpdateWithdrawalDelay {
+            admin,
+            config: self.config_address(),
+        };
+        let data = drift_client::instruction::UpdateWithdrawalDelay { withdrawal_delay };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn propose_withdrawal_destination(
+        &self,
+        admin: Pubkey,
+        destination: Pubkey,
+    ) -> Instruction {
+        let data = drift_client::instruction::ProposeWithdrawalDestination { destination };
+        self.instruction(
+            self.update_withdrawal_destinations_accounts(admin),
+            data.data(),
+        )
+    }
+
+    pub fn accept_withdrawal_destination(&self, admin: Pubkey) -> Instruction {
+        let data = drift_client::instruction::AcceptWithdrawalDestination {};
+        self.instruction(
+            self.update_withdrawal_destinations_accounts(admin),
+            data.data(),
+        )
+    }
+
+    pub fn remove_withdrawal_destination(&self, admin: Pubkey, destination: Pubkey) -> Instruction {
+        let data = drift_client::instruction::RemoveWithdrawalDestination { destination };
+        self.instruction(
+            self.update_withdrawal_destinations_accounts(admin),
+            data.data(),
+        )
+    }
+
+    pub fn initialize_vault_depositor(&self, depositor: Pubkey) -> Instruction {
+        let config = self.config_address();
+        let (vault_depositor, vault_depositor_nonce) =
+            pda::get_vault_depositor_address(&self.program_id, &config, &depositor);
+        let accounts = drift_client::accounts::InitializeVaultDepositor {
+            depositor,
+            config,
+            vault_depositor,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        };
+        let data = drift_client::instruction::InitializeVaultDepositor {
+            _vault_depositor_nonce: vault_depositor_nonce,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn vault_deposit(
+        &self,
+        config: &Config,
+        depositor: Pubkey,
+        depositor_collateral_account: Pubkey,
+        amount: u64,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::VaultDeposit {
+            depositor,
+            depositor_collateral_account,
+            vault_depositor: self.vault_depositor_address(&depositor),
+            config: self.config_address(),
+            collateral_vault: config.collateral_vault,
+            clearing_house_collateral: self.clearing_house_collateral(config),
+        };
+        let data = drift_client::instruction::VaultDeposit { amount };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn vault_withdraw(
+        &self,
+        config: &Config,
+        depositor: Pubkey,
+        depositor_collateral_account: Pubkey,
+        shares: u128,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::VaultWithdraw {
+            depositor,
+            depositor_collateral_account,
+            vault_depositor: self.vault_depositor_address(&depositor),
+            config: self.config_address(),
+            collateral_vault: config.collateral_vault,
+            clearing_house_collateral: self.clearing_house_collateral(config),
+        };
+        let data = drift_client::instruction::VaultWithdraw { shares };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn initialize_trigger_order(
+        &self,
+        config: &Config,
+        admin: Pubkey,
+        market_index: u64,
+        stop_loss_price: u128,
+        take_profit_price: u128,
+        keeper_fee: u64,
+    ) -> Instruction {
+        let config_address = self.config_address();
+        let (trigger_order, trigger_order_nonce) =
+            pda::get_trigger_order_address(&self.program_id, &config_address, market_index);
+        let accounts = drift_client::accounts::InitializeTriggerOrder {
+            admin,
+            config: config_address,
+            trigger_order,
+            clearing_house_user_positions: config.clearing_house_user_positions,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        };
+        let data = drift_client::instruction::InitializeTriggerOrder {
+            _trigger_order_nonce: trigger_order_nonce,
+            market_index,
+            stop_loss_price,
+            take_profit_price,
+            keeper_fee,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn update_trigger_order(
+        &self,
+        config: &Config,
+        admin: Pubkey,
+        market_index: u64,
+        stop_loss_price: u128,
+        take_profit_price: u128,
+        keeper_fee: u64,
+    ) -> Instruction {
+        let accounts = drift_client::accounts::UpdateTriggerOrder {
+            admin,
+            config: self.config_address(),
+            trigger_order: self.trigger_order_address(market_index),
+            clearing_house_user_positions: config.clearing_house_user_positions,
+        };
+        let data = drift_client::instruction::UpdateTriggerOrder {
+            stop_loss_price,
+            take_profit_price,
+            keeper_fee,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn execute_trigger(
+        &self,
+        config: &Config,
+        keeper: Pubkey,
+        keeper_collateral_account: Pubkey,
+        oracle: Pubkey,
+        market_index: u64,
+        oracles: &[Pubkey],
+    ) -> Instruction {
+        let accounts = drift_client::accounts::ExecuteTrigger {
+            keeper,
+            keeper_collateral_account,
+            config: self.config_address(),
+            trigger_order: self.trigger_order_address(market_index),
+            collateral_vault: config.collateral_vault,
+            clearing_house: self.clearing_house_trade(config),
+            clearing_house_collateral: self.clearing_house_collateral(config),
+            oracle,
+        };
+        let mut account_metas = accounts.to_account_metas(None);
+        account_metas.extend(oracle_account_metas(oracles));
+        let data = drift_client::instruction::ExecuteTrigger {};
+        self.instruction(account_metas, data.data())
+    }
+
+    pub fn initialize_order_book(&self, admin: Pubkey) -> Instruction {
+        let config = self.config_address();
+        let (order_book, order_book_nonce) = pda::get_order_book_address(&self.program_id, &config);
+        let accounts = drift_client::accounts::InitializeOrderBook {
+            admin,
+            config,
+            order_book,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        };
+        let data = drift_client::instruction::InitializeOrderBook {
+            _order_book_nonce: order_book_nonce,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn place_order(
+        &self,
+        admin: Pubkey,
+        direction: PositionDirection,
+        quote_asset_amount: u128,
+        market_index: u64,
+        price: u128,
+        expiry_ts: i64,
+    ) -> Instruction {
+        let data = drift_client::instruction::PlaceOrder {
+            direction,
+            quote_asset_amount,
+            market_index,
+            price,
+            expiry_ts,
+        };
+        self.instruction(self.manage_orders_accounts(admin), data.data())
+    }
+
+    pub fn cancel_order(&self, admin: Pubkey, order_id: u64) -> Instruction {
+        let data = drift_client::instruction::CancelOrder { order_id };
+        self.instruction(self.manage_orders_accounts(admin), data.data())
+    }
+
+    pub fn fill_order(
+        &self,
+        config: &Config,
+        keeper: Pubkey,
+        oracle: Pubkey,
+        order_id: u64,
+    ) -> Instruction {
+        let accounts = drift_client::accounts::FillOrder {
+            keeper,
+            config: self.config_address(),
+            risk_limits: config.risk_limits,
+            order_book: self.order_book_address(),
+            clearing_house: self.clearing_house_trade(config),
+            oracle,
+        };
+        let data = drift_client::instruction::FillOrder { order_id };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn initialize_twap_order(&self, admin: Pubkey, market_index: u64) -> Instruction {
+        let config = self.config_address();
+        let (twap_order, twap_order_nonce) =
+            pda::get_twap_order_address(&self.program_id, &config, market_index);
+        let accounts = drift_client::accounts::InitializeTwapOrder {
+            admin,
+            config,
+            twap_order,
+            rent: sysvar::rent::ID,
+            system_program: system_program::ID,
+        };
+        let data = drift_client::instruction::InitializeTwapOrder {
+            _twap_order_nonce: twap_order_nonce,
+            market_index,
+        };
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    pub fn start_twap(
+        &self,
+        admin: Pubkey,
+        market_index: u64,
+        direction: PositionDirection,
+        total_quote_asset_amount: u128,
+        num_slices: u64,
+        duration: i64,
+    ) -> Instruction {
+        let data = drift_client::instruction::StartTwap {
+            direction,
+            total_quote_asset_amount,
+            num_slices,
+            duration,
+        };
+        self.instruction(
+            self.manage_twap_order_accounts(admin, market_index),
+            data.data(),
+        )
+    }
+
+    pub fn cancel_twap(&self, admin: Pubkey, market_index: u64) -> Instruction {
+        let data = drift_client::instruction::CancelTwap {};
+        self.instruction(
+            self.manage_twap_order_accounts(admin, market_index),
+            data.data(),
+        )
+    }
+
+    pub fn crank_twap(
+        &self,
+        config: &Config,
+        keeper: Pubkey,
+        oracle: Pubkey,
+        market_index: u64,
+    ) -> Instruction {
+        let accounts = drift_client::accounts::CrankTwap {
+            keeper,
+            config: self.config_address(),
+            risk_limits: config.risk_limits,
+            twap_order: self.twap_order_address(market_index),
+            clearing_house: self.clearing_house_trade(config),
+            oracle,
+        };
+        let data = drift_client::instruction::CrankTwap {};
+        self.instruction(accounts.to_account_metas(None), data.data())
+    }
+
+    fn manage_twap_order_accounts(&self, admin: Pubkey, market_index: u64) -> Vec<AccountMeta> {
+        drift_client::accounts::ManageTwapOrder {
+            admin,
+            config: self.config_address(),
+            twap_order: self.twap_order_address(market_index),
+        }
+        .to_account_metas(None)
+    }
+
+    fn update_withdrawal_destinations_accounts(&self, admin: Pubkey) -> Vec<AccountMeta> {
+        drift_client::accounts::UpdateWithdrawalDestinations {
+            admin,
+            config: self.config_address(),
+        }
+        .to_account_metas(None)
+    }
+
+    fn manage_orders_accounts(&self, admin: Pubkey) -> Vec<AccountMeta> {
+        drift_client::accounts::ManageOrders {
+            admin,
+            config: self.config_address(),
+            order_book: self.order_book_address(),
+        }
+        .to_account_metas(None)
+    }
+
+    fn open_position_accounts(
+        &self,
+        config: &Config,
+        trader: Pubkey,
+        oracle: Pubkey,
+    ) -> drift_client::accounts::OpenPosition {
+        drift_client::accounts::OpenPosition {
+            trader,
+            config: self.config_address(),
+            risk_limits: config.risk_limits,
+            clearing_house: self.clearing_house_trade(config),
+            oracle,
+        }
+    }
+
+    fn clearing_house_trade(&self, config: &Config) -> drift_client::accounts::ClearingHouseTrade {
+        drift_client::accounts::ClearingHouseTrade {
+            authority: config.authority,
+            state: self.clearing_house.state,
+            user: config.clearing_house_user,
+            user_positions: config.clearing_house_user_positions,
+            markets: self.clearing_house.markets,
+            funding_payment_history: self.clearing_house.funding_payment_history,
+            funding_rate_history: self.clearing_house.funding_rate_history,
+            trade_history: self.clearing_house.trade_history,
+            program: self.clearing_house.program_id,
+        }
+    }
+
+    fn clearing_house_collateral(
+        &self,
+        config: &Config,
+    ) -> drift_client::accounts::ClearingHouseCollateral {
+        drift_client::accounts::ClearingHouseCollateral {
+            authority: config.authority,
+            state: self.clearing_house.state,
+            user: config.clearing_house_user,
+            user_positions: config.clearing_house_user_positions,
+            markets: self.clearing_house.markets,
+            collateral_vault: self.clearing_house.collateral_vault,
+            collateral_vault_authority: self.clearing_house.collateral_vault_authority,
+            insurance_vault: self.clearing_house.insurance_vault,
+            insurance_vault_authority: self.clearing_house.insurance_vault_authority,
+            funding_payment_history: self.clearing_house.funding_payment_history,
+            deposit_history: self.clearing_house.deposit_history,
+            token_program: anchor_spl::token::ID,
+            program: self.clearing_house.program_id,
+        }
+    }
+
+    fn append_optional_accounts(
+        account_metas: &mut Vec<AccountMeta>,
+        discount_token: Option<Pubkey>,
+        referrer: Option<Pubkey>,
+    ) -> ManagePositionOptionalAccounts {
+        if let Some(discount_token) = discount_token {
+            account_metas.push(AccountMeta::new_readonly(discount_token, false));
+        }
+        if let Some(referrer) = referrer {
+            account_metas.push(AccountMeta::new(referrer, false));
+        }
+        ManagePositionOptionalAccounts {
+            discount_token: discount_token.is_some(),
+            referrer: referrer.is_some(),
+        }
+    }
+
+    fn instruction(&self, accounts: Vec<AccountMeta>, data: Vec<u8>) -> Instruction {
+        Instruction {
+            program_id: self.program_id,
+            accounts,
+            data,
+        }
+    }
+}
+
+pub fn read_config(data: &[u8]) -> std::result::Result<Config, ProgramError> {
+    Config::try_deserialize(&mut &data[..])
+}
+
+pub fn read_risk_limits(data: &[u8]) -> std::result::Result<RiskLimits, ProgramError> {
+    RiskLimits::try_deserialize(&mut &data[..])
+}
+
+pub fn read_vault_depositor(data: &[u8]) -> std::result::Result<VaultDepositor, ProgramError> {
+    VaultDepositor::try_deserialize(&mut &data[..])
+}
+
+pub fn read_trigger_order(data: &[u8]) -> std::result::Result<TriggerOrder, ProgramError> {
+    TriggerOrder::try_deserialize(&mut &data[..])
+}
+
+pub fn read_order_book(data: &[u8]) -> std::result::Result<OrderBook, ProgramError> {
+    OrderBook::try_deserialize(&mut &data[..])
+}
+
+pub fn read_twap_order(data: &[u8]) -> std::result::Result<TwapOrder, ProgramError> {
+    TwapOrder::try_deserialize(&mut &data[..])
+}
+
+pub fn read_withdrawal_request(
+    data: &[u8],
+) -> std::result::Result<WithdrawalRequest, ProgramError> {
+    WithdrawalRequest::try_deserialize(&mut &data[..])
+}
+
+/// Read only metas for oracles passed in remaining accounts
+fn oracle_account_metas(oracles: &[Pubkey]) -> impl Iterator<Item = AccountMeta> + '_ {
+    oracles
+        .iter()
+        .map(|oracle| AccountMeta::new_readonly(*oracle, false))
+}
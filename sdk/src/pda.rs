@@ -0,0 +1,101 @@
+use anchor_lang::prelude::Pubkey;
+
+pub fn get_config_address(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    sub_account_id: u16,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"drift_client".as_ref(),
+            owner.as_ref(),
+            sub_account_id.to_le_bytes().as_ref(),
+        ],
+        program_id,
+    )
+}
+
+pub fn get_collateral_vault_address(program_id: &Pubkey, config: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"collateral_vault".as_ref(), config.as_ref()], program_id)
+}
+
+pub fn get_authority_address(
+    program_id: &Pubkey,
+    clearing_house_program_id: &Pubkey,
+    config: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[clearing_house_program_id.as_ref(), config.as_ref()],
+        program_id,
+    )
+}
+
+pub fn get_risk_limits_address(program_id: &Pubkey, config: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"risk_limits".as_ref(), config.as_ref()], program_id)
+}
+
+pub fn get_vault_depositor_address(
+    program_id: &Pubkey,
+    config: &Pubkey,
+    depositor: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"vault_depositor".as_ref(),
+            config.as_ref(),
+            depositor.as_ref(),
+        ],
+        program_id,
+    )
+}
+
+pub fn get_trigger_order_address(
+    program_id: &Pubkey,
+    config: &Pubkey,
+    market_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"trigger_order".as_ref(),
+            config.as_ref(),
+            market_index.to_le_bytes().as_ref(),
+        ],
+        program_id,
+    )
+}
+
+pub fn get_order_book_address(program_id: &Pubkey, config: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"order_book".as_ref(), config.as_ref()], program_id)
+}
+
+pub fn get_twap_order_address(
+    program_id: &Pubkey,
+    config: &Pubkey,
+    market_index: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"twap_order".as_ref(),
+            config.as_ref(),
+            market_index.to_le_bytes().as_ref(),
+        ],
+        program_id,
+    )
+}
+
+pub fn get_withdrawal_request_address(program_id: &Pubkey, config: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"withdrawal_request".as_ref(), config.as_ref()],
+        program_id,
+    )
+}
+
+pub fn get_clearing_house_user_address(
+    clearing_house_program_id: &Pubkey,
+    authority: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"user".as_ref(), authority.as_ref()],
+        clearing_house_program_id,
+    )
+}
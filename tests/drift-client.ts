@@ -6,6 +6,7 @@ import { Admin } from '../deps/protocol-v1/sdk/src';
 import { mockUSDCMint, mockUserUSDCAccount, mockOracle } from './testHelpers';
 
 import { Keypair, PublicKey } from '@solana/web3.js';
+import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
 
 import { DriftClient } from '../ts/driftClient';
 import { BN } from '@project-serum/anchor';
@@ -71,7 +72,7 @@ describe('drift client', () => {
 	});
 
 	it('initialize', async () => {
-		await driftClient.initialize();
+		await driftClient.initialize(userUSDCAccount.publicKey);
 		const config = await driftClient.getConfig();
 		assert(config.admin.equals(provider.wallet.publicKey));
 		assert(
@@ -80,6 +81,66 @@ describe('drift client', () => {
 			)
 		);
 		assert(config.authority.equals(await driftClient.getAuthorityPublicKey()));
+		assert(config.owner.equals(provider.wallet.publicKey));
+		assert(config.subAccountId === 0);
+		assert(config.withdrawalDestinations[0].equals(userUSDCAccount.publicKey));
+	});
+
+	it('initialize sub account', async () => {
+		const subAccountDriftClient = new DriftClient(
+			program,
+			clearingHouse,
+			provider.wallet.publicKey,
+			1
+		);
+		await subAccountDriftClient.initialize(userUSDCAccount.publicKey);
+		const config = await subAccountDriftClient.getConfig();
+		assert(config.subAccountId === 1);
+		assert(
+			!config.authority.equals(await driftClient.getAuthorityPublicKey())
+		);
+		assert(
+			!config.collateralVault.equals(
+				await driftClient.getCollateralVaultPublicKey()
+			)
+		);
+	});
+
+	it('transfer admin', async () => {
+		const newAdmin = Keypair.generate();
+		await provider.connection.confirmTransaction(
+			await provider.connection.requestAirdrop(newAdmin.publicKey, 10 ** 9)
+		);
+		const subAccountDriftClient = new DriftClient(
+			program,
+			clearingHouse,
+			provider.wallet.publicKey,
+			1
+		);
+		await subAccountDriftClient.proposeAdmin(newAdmin.publicKey);
+		let config = await subAccountDriftClient.getConfig();
+		assert(config.admin.equals(provider.wallet.publicKey));
+		assert(config.pendingAdmin.equals(newAdmin.publicKey));
+
+		const newAdminProgram = new anchor.Program(
+			program.idl,
+			program.programId,
+			new anchor.Provider(
+				provider.connection,
+				new anchor.Wallet(newAdmin),
+				anchor.Provider.defaultOptions()
+			)
+		);
+		const newAdminDriftClient = new DriftClient(
+			newAdminProgram,
+			clearingHouse,
+			provider.wallet.publicKey,
+			1
+		);
+		await newAdminDriftClient.acceptAdmin();
+		config = await subAccountDriftClient.getConfig();
+		assert(config.admin.equals(newAdmin.publicKey));
+		assert(config.pendingAdmin.equals(new PublicKey(0)));
 	});
 
 	it('initialize user', async () => {
@@ -91,6 +152,138 @@ describe('drift client', () => {
 		);
 		const expectedAuthority = await driftClient.getAuthorityPublicKey();
 		assert(userAccount.authority.equals(expectedAuthority));
+		const config = await driftClient.getConfig();
+		assert(config.clearingHouseUser.equals(userAccountPublicKey));
+		assert(config.clearingHouseUserPositions.equals(userAccount.positions));
+	});
+
+	it('mismatched clearing house user fails', async () => {
+		const otherDriftClient = new DriftClient(
+			program,
+			clearingHouse,
+			provider.wallet.publicKey,
+			2
+		);
+		await otherDriftClient.initialize(userUSDCAccount.publicKey);
+		await otherDriftClient.initializeUser();
+		const otherUserAccount = await otherDriftClient.getUserAccount();
+
+		const clearingHouseState = clearingHouse.getStateAccount();
+		let failed = false;
+		try {
+			await program.rpc.depositCollateral(usdcAmount, {
+				accounts: {
+					admin: provider.wallet.publicKey,
+					adminCollateralAccount: userUSDCAccount.publicKey,
+					config: await driftClient.getConfigPublicKey(),
+					collateralVault: await driftClient.getCollateralVaultPublicKey(),
+					clearingHouseCollateral: {
+						authority: await driftClient.getAuthorityPublicKey(),
+						state: await clearingHouse.getStatePublicKey(),
+						user: await otherDriftClient.getClearingHouseUserAccountPublicKey(),
+						userPositions: otherUserAccount.positions,
+						markets: clearingHouseState.markets,
+						collateralVault: clearingHouseState.collateralVault,
+						collateralVaultAuthority:
+							clearingHouseState.collateralVaultAuthority,
+						insuranceVault: clearingHouseState.insuranceVault,
+						insuranceVaultAuthority: clearingHouseState.insuranceVaultAuthority,
+						fundingPaymentHistory: clearingHouseState.fundingPaymentHistory,
+						depositHistory: clearingHouseState.depositHistory,
+						tokenProgram: TOKEN_PROGRAM_ID,
+						program: clearingHouse.program.programId,
+					},
+				},
+			});
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+		const tokenBalance = new BN(
+			(
+				await provider.connection.getTokenAccountBalance(
+					userUSDCAccount.publicKey
+				)
+			).value.amount
+		);
+		assert(tokenBalance.eq(usdcAmount));
+	});
+
+	it('mismatched clearing house vault and positions fail', async () => {
+		const otherDriftClient = new DriftClient(
+			program,
+			clearingHouse,
+			provider.wallet.publicKey,
+			2
+		);
+		const otherUserAccount = await otherDriftClient.getUserAccount();
+		const userAccount = await driftClient.getUserAccount();
+		const clearingHouseState = clearingHouse.getStateAccount();
+		const clearingHouseCollateral = {
+			authority: await driftClient.getAuthorityPublicKey(),
+			state: await clearingHouse.getStatePublicKey(),
+			user: await driftClient.getClearingHouseUserAccountPublicKey(),
+			userPositions: userAccount.positions,
+			markets: clearingHouseState.markets,
+			collateralVault: clearingHouseState.collateralVault,
+			collateralVaultAuthority: clearingHouseState.collateralVaultAuthority,
+			insuranceVault: clearingHouseState.insuranceVault,
+			insuranceVaultAuthority: clearingHouseState.insuranceVaultAuthority,
+			fundingPaymentHistory: clearingHouseState.fundingPaymentHistory,
+			depositHistory: clearingHouseState.depositHistory,
+			tokenProgram: TOKEN_PROGRAM_ID,
+			program: clearingHouse.program.programId,
+		};
+
+		const assertDepositFails = async (mismatchedAccounts, msg: string) => {
+			try {
+				await program.rpc.depositCollateral(usdcAmount, {
+					accounts: {
+						admin: provider.wallet.publicKey,
+						adminCollateralAccount: userUSDCAccount.publicKey,
+						config: await driftClient.getConfigPublicKey(),
+						collateralVault: await driftClient.getCollateralVaultPublicKey(),
+						clearingHouseCollateral: {
+							...clearingHouseCollateral,
+							...mismatchedAccounts,
+						},
+					},
+				});
+			} catch (e) {
+				assert(e.msg === msg);
+				return;
+			}
+			assert(false);
+		};
+
+		await assertDepositFails(
+			{ collateralVault: clearingHouseState.insuranceVault },
+			'Clearing house collateral vault does not match clearing house state'
+		);
+		await assertDepositFails(
+			{ userPositions: otherUserAccount.positions },
+			'Clearing house user positions does not match config'
+		);
+	});
+
+	it('update risk limits', async () => {
+		const maxLeverage = new BN(50000); // 5x
+		const maxOracleSlippage = new BN(1000); // 10%
+		await driftClient.updateRiskLimits(maxLeverage, maxOracleSlippage, [
+			{
+				enabled: true,
+				marketIndex,
+				maxNotional: usdcAmount.mul(new BN(5)),
+			},
+		]);
+		const config = await driftClient.getConfig();
+		assert(
+			config.riskLimits.equals(await driftClient.getRiskLimitsPublicKey())
+		);
+		const riskLimits = await driftClient.getRiskLimits();
+		assert(riskLimits.maxLeverage.eq(maxLeverage));
+		assert(riskLimits.markets[0].enabled);
+		assert(!riskLimits.markets[1].enabled);
 	});
 
 	it('deposit collateral', async () => {
@@ -99,6 +292,113 @@ describe('drift client', () => {
 		assert(usdcAmount.eq(userAccount.collateral));
 	});
 
+	it('delegate cannot withdraw collateral', async () => {
+		const delegate = Keypair.generate();
+		await provider.connection.confirmTransaction(
+			await provider.connection.requestAirdrop(delegate.publicKey, 10 ** 9)
+		);
+		await driftClient.updateDelegate(delegate.publicKey);
+		const config = await driftClient.getConfig();
+		assert(config.delegate.equals(delegate.publicKey));
+
+		const delegateProgram = new anchor.Program(
+			program.idl,
+			program.programId,
+			new anchor.Provider(
+				provider.connection,
+				new anchor.Wallet(delegate),
+				anchor.Provider.defaultOptions()
+			)
+		);
+		const delegateDriftClient = new DriftClient(
+			delegateProgram,
+			clearingHouse,
+			provider.wallet.publicKey
+		);
+		let failed = false;
+		try {
+			await delegateDriftClient.withdrawCollateral(
+				usdcAmount,
+				userUSDCAccount.publicKey
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+		const userAccount = await driftClient.getUserAccount();
+		assert(usdcAmount.eq(userAccount.collateral));
+	});
+
+	it('oracle guards', async () => {
+		// the mock oracle's confidence is 10% of its price
+		await driftClient.updateOracleGuards({
+			maxStalenessSlots: ZERO,
+			maxConfRatio: new BN(500),
+			maxMarkOracleDivergence: ZERO,
+		});
+		let config = await driftClient.getConfig();
+		assert(config.oracleGuards.maxConfRatio.eq(new BN(500)));
+
+		let failed = false;
+		try {
+			await driftClient.openPosition(
+				PositionDirection.LONG,
+				usdcAmount,
+				marketIndex
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.updateOracleGuards({
+			maxStalenessSlots: ZERO,
+			maxConfRatio: new BN(2000),
+			maxMarkOracleDivergence: new BN(1000),
+		});
+		config = await driftClient.getConfig();
+		assert(config.oracleGuards.maxMarkOracleDivergence.eq(new BN(1000)));
+	});
+
+	it('open position exceeding max leverage fails', async () => {
+		let failed = false;
+		try {
+			await driftClient.openPosition(
+				PositionDirection.LONG,
+				usdcAmount.mul(new BN(10)),
+				marketIndex
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+		const userPositionsAccount = await driftClient.getUserPositionsAccount();
+		assert(ZERO.eq(userPositionsAccount.positions[0].quoteAssetAmount));
+	});
+
+	it('rebalance reverts every leg when one fails', async () => {
+		let failed = false;
+		try {
+			await driftClient.rebalance([
+				{
+					marketIndex,
+					direction: PositionDirection.LONG,
+					quoteAssetAmount: usdcAmount.div(new BN(2)),
+				},
+				{
+					marketIndex,
+					direction: PositionDirection.LONG,
+					quoteAssetAmount: usdcAmount.mul(new BN(10)),
+				},
+			]);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+		const userPositionsAccount = await driftClient.getUserPositionsAccount();
+		assert(ZERO.eq(userPositionsAccount.positions[0].quoteAssetAmount));
+	});
+
 	it('open position', async () => {
 		await driftClient.openPosition(
 			PositionDirection.LONG,
@@ -110,6 +410,64 @@ describe('drift client', () => {
 		assert(usdcAmount.eq(position.quoteAssetAmount));
 	});
 
+	it('trigger order waits for its price', async () => {
+		const stopLossPrice = MARK_PRICE_PRECISION.div(new BN(2));
+		const takeProfitPrice = MARK_PRICE_PRECISION.mul(new BN(2));
+		const keeperFee = new BN(10000);
+		await driftClient.initializeTriggerOrder(
+			marketIndex,
+			stopLossPrice,
+			takeProfitPrice,
+			keeperFee
+		);
+		const triggerOrder = await driftClient.getTriggerOrder(marketIndex);
+		assert(triggerOrder.stopLossPrice.eq(stopLossPrice));
+		assert(triggerOrder.takeProfitPrice.eq(takeProfitPrice));
+		// raising the fee from 0 waits out the withdrawal delay
+		assert(triggerOrder.pendingKeeperFee.eq(keeperFee));
+
+		let failed = false;
+		try {
+			await driftClient.executeTrigger(marketIndex, userUSDCAccount.publicKey);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		const userPositionsAccount = await driftClient.getUserPositionsAccount();
+		assert(usdcAmount.eq(userPositionsAccount.positions[0].quoteAssetAmount));
+	});
+
+	it('reduce position cannot flip or increase position', async () => {
+		let failed = false;
+		try {
+			await driftClient.reducePosition(
+				PositionDirection.SHORT,
+				usdcAmount.mul(new BN(2)),
+				marketIndex
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		failed = false;
+		try {
+			await driftClient.reducePosition(
+				PositionDirection.LONG,
+				usdcAmount.div(new BN(2)),
+				marketIndex
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		const userPositionsAccount = await driftClient.getUserPositionsAccount();
+		const position = userPositionsAccount.positions[0];
+		assert(usdcAmount.eq(position.quoteAssetAmount));
+	});
+
 	it('close position', async () => {
 		await driftClient.closePosition(marketIndex);
 		const userPositionsAccount = await driftClient.getUserPositionsAccount();
@@ -117,6 +475,73 @@ describe('drift client', () => {
 		assert(ZERO.eq(position.quoteAssetAmount));
 	});
 
+	it('target position already reached does not trade', async () => {
+		await driftClient.setTargetPosition(marketIndex, ZERO, new BN(100));
+		const userPositionsAccount = await driftClient.getUserPositionsAccount();
+		assert(ZERO.eq(userPositionsAccount.positions[0].baseAssetAmount));
+		assert(ZERO.eq(userPositionsAccount.positions[0].quoteAssetAmount));
+	});
+
+	it('limit orders', async () => {
+		await driftClient.initializeOrderBook();
+		// the mark price is 1, so a long at 0.5 rests
+		await driftClient.placeOrder(
+			PositionDirection.LONG,
+			usdcAmount,
+			marketIndex,
+			MARK_PRICE_PRECISION.div(new BN(2))
+		);
+		let orderBook = await driftClient.getOrderBook();
+		const order = orderBook.orders.find((order) => !order.id.eq(ZERO));
+		assert(order.price.eq(MARK_PRICE_PRECISION.div(new BN(2))));
+
+		let failed = false;
+		try {
+			await driftClient.fillOrder(order.id);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.cancelOrder(order.id);
+		orderBook = await driftClient.getOrderBook();
+		assert(orderBook.orders.every((order) => order.id.eq(ZERO)));
+	});
+
+	it('twap order', async () => {
+		await driftClient.initializeTwapOrder(marketIndex);
+
+		let failed = false;
+		try {
+			await driftClient.startTwap(
+				marketIndex,
+				PositionDirection.LONG,
+				usdcAmount,
+				new BN(17),
+				new BN(60 * 60)
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.startTwap(
+			marketIndex,
+			PositionDirection.LONG,
+			usdcAmount,
+			new BN(4),
+			new BN(60 * 60)
+		);
+		let twapOrder = await driftClient.getTwapOrder(marketIndex);
+		assert(twapOrder.numSlices.eq(new BN(4)));
+		assert(twapOrder.sliceInterval.eq(new BN(15 * 60)));
+
+		await driftClient.cancelTwap(marketIndex);
+		twapOrder = await driftClient.getTwapOrder(marketIndex);
+		assert(twapOrder.numSlices.eq(ZERO));
+		assert(twapOrder.slicesFilled.eq(ZERO));
+	});
+
 	it('withdraw collateral', async () => {
 		const withdrawAmount = new BN(9980000);
 		await driftClient.withdrawCollateral(
@@ -134,4 +559,104 @@ describe('drift client', () => {
 		);
 		assert(adminTokenBalance.eq(withdrawAmount));
 	});
+
+	it('vault deposit', async () => {
+		await driftClient.initializeVaultDepositor();
+		const depositAmount = new BN(5000000);
+		await driftClient.vaultDeposit(depositAmount, userUSDCAccount.publicKey);
+		const vaultDepositor = await driftClient.getVaultDepositor();
+		assert(vaultDepositor.shares.eq(depositAmount));
+		const config = await driftClient.getConfig();
+		assert(config.totalShares.eq(depositAmount));
+		const userAccount = await driftClient.getUserAccount();
+		assert(userAccount.collateral.eq(depositAmount));
+	});
+
+	it('vault withdraw', async () => {
+		const vaultDepositor = await driftClient.getVaultDepositor();
+		await driftClient.vaultWithdraw(
+			vaultDepositor.shares,
+			userUSDCAccount.publicKey
+		);
+		const vaultDepositorAfter = await driftClient.getVaultDepositor();
+		assert(vaultDepositorAfter.shares.eq(ZERO));
+		const config = await driftClient.getConfig();
+		assert(config.totalShares.eq(ZERO));
+		const adminTokenBalance = new BN(
+			(
+				await provider.connection.getTokenAccountBalance(
+					userUSDCAccount.publicKey
+				)
+			).value.amount
+		);
+		assert(adminTokenBalance.eq(new BN(9980000)));
+	});
+
+	it('withdrawal timelock', async () => {
+		await driftClient.updateWithdrawalDelay(new BN(60 * 60));
+		const config = await driftClient.getConfig();
+		assert(config.withdrawalDelay.eq(new BN(60 * 60)));
+
+		let failed = false;
+		try {
+			await driftClient.updateWithdrawalDelay(ZERO);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		failed = false;
+		try {
+			await driftClient.withdrawCollateral(
+				new BN(1),
+				userUSDCAccount.publicKey
+			);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.requestWithdrawal(new BN(1));
+		const withdrawalRequest = await driftClient.getWithdrawalRequest();
+		assert(withdrawalRequest.amount.eq(new BN(1)));
+
+		failed = false;
+		try {
+			await driftClient.claimWithdrawal(userUSDCAccount.publicKey);
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.cancelWithdrawal();
+	});
+
+	it('withdrawal destination allow-list', async () => {
+		const emptyPublicKey = new PublicKey(0);
+		const destination = Keypair.generate().publicKey;
+		await driftClient.proposeWithdrawalDestination(destination);
+		let config = await driftClient.getConfig();
+		assert(config.pendingWithdrawalDestination.equals(destination));
+
+		// the withdrawal destination delay has not passed
+		let failed = false;
+		try {
+			await driftClient.acceptWithdrawalDestination();
+		} catch (e) {
+			failed = true;
+		}
+		assert(failed);
+
+		await driftClient.proposeWithdrawalDestination(emptyPublicKey);
+		config = await driftClient.getConfig();
+		assert(config.pendingWithdrawalDestination.equals(emptyPublicKey));
+		assert(config.withdrawalDestinations[0].equals(userUSDCAccount.publicKey));
+		assert(
+			config.withdrawalDestinations
+				.slice(1)
+				.every((withdrawalDestination) =>
+					withdrawalDestination.equals(emptyPublicKey)
+				)
+		);
+	});
 });
@@ -283,6 +283,178 @@ export const setFeedPrice = async (
 		accounts: { price: priceFeed },
 	});
 };
+export const setFeedTwap = async (
+	oracleProgram: Program,
+	newTwap: number,
+	priceFeed: PublicKey
+) => {
+	const info = await oracleProgram.provider.connection.getAccountInfo(
+		priceFeed
+	);
+	const data = parsePriceData(info.data);
+	await oracleProgram.rpc.setTwap(new BN(newTwap * 10 ** -data.exponent), {
+		accounts: { price: priceFeed },
+	});
+};
+export const addFeedPublisher = async (
+	oracleProgram: Program,
+	publisher: PublicKey,
+	priceFeed: PublicKey
+) => {
+	await oracleProgram.rpc.addPublisher(publisher, {
+		accounts: { price: priceFeed },
+	});
+};
+export const submitFeedPrice = async (
+	oracleProgram: Program,
+	publisher: Keypair,
+	newPrice: number,
+	newConf: number,
+	priceFeed: PublicKey
+) => {
+	const info = await oracleProgram.provider.connection.getAccountInfo(
+		priceFeed
+	);
+	const data = parsePriceData(info.data);
+	await oracleProgram.rpc.submitPrice(
+		new BN(newPrice * 10 ** -data.exponent),
+		new BN(newConf * 10 ** -data.exponent),
+		{
+			accounts: { price: priceFeed, publisher: publisher.publicKey },
+			signers: [publisher],
+		}
+	);
+};
+export const setFeedConf = async (
+	oracleProgram: Program,
+	newConf: number,
+	priceFeed: PublicKey
+) => {
+	const info = await oracleProgram.provider.connection.getAccountInfo(
+		priceFeed
+	);
+	const data = parsePriceData(info.data);
+	await oracleProgram.rpc.setConf(new BN(newConf * 10 ** -data.exponent), {
+		accounts: { price: priceFeed },
+	});
+};
+export class PriceStatus {
+	static readonly UNKNOWN = { unknown: {} };
+	static readonly TRADING = { trading: {} };
+	static readonly HALTED = { halted: {} };
+	static readonly AUCTION = { auction: {} };
+}
+export const setFeedStatus = async (
+	oracleProgram: Program,
+	status: Record<string, unknown>,
+	priceFeed: PublicKey
+) => {
+	await oracleProgram.rpc.setStatus(status, {
+		accounts: { price: priceFeed },
+	});
+};
+export const setFeedSlots = async (
+	oracleProgram: Program,
+	pubSlot: number,
+	validSlot: number,
+	priceFeed: PublicKey
+) => {
+	await oracleProgram.rpc.setSlots(new BN(pubSlot), new BN(validSlot), {
+		accounts: { price: priceFeed },
+	});
+};
+const createPythAccount = async (
+	oracleProgram: Program,
+	space: number
+): Promise<Keypair> => {
+	const account = Keypair.generate();
+	const transaction = new Transaction().add(
+		anchor.web3.SystemProgram.createAccount({
+			fromPubkey: oracleProgram.provider.wallet.publicKey,
+			newAccountPubkey: account.publicKey,
+			space,
+			lamports:
+				await oracleProgram.provider.connection.getMinimumBalanceForRentExemption(
+					space
+				),
+			programId: oracleProgram.programId,
+		})
+	);
+	await oracleProgram.provider.send(transaction, [account]);
+	return account;
+};
+export const createMapping = async (
+	oracleProgram: Program
+): Promise<PublicKey> => {
+	const mapping = await createPythAccount(oracleProgram, 20536);
+	await oracleProgram.rpc.initializeMapping({
+		accounts: { mapping: mapping.publicKey },
+	});
+	return mapping.publicKey;
+};
+export const createProduct = async (
+	oracleProgram: Program,
+	attributes: { key: string; value: string }[],
+	mapping: PublicKey
+): Promise<PublicKey> => {
+	const product = await createPythAccount(oracleProgram, 512);
+	await oracleProgram.rpc.initializeProduct(attributes, {
+		accounts: { product: product.publicKey },
+	});
+	await oracleProgram.rpc.addProduct({
+		accounts: { mapping, product: product.publicKey },
+	});
+	return product.publicKey;
+};
+export const addFeedToProduct = async (
+	oracleProgram: Program,
+	priceFeed: PublicKey,
+	product: PublicKey
+) => {
+	await oracleProgram.rpc.addPrice({
+		accounts: { product, price: priceFeed },
+	});
+};
+export const createPricePath = async (
+	oracleProgram: Program,
+	priceFeed: PublicKey,
+	points: { slot: BN; price: BN; conf: BN }[]
+): Promise<PublicKey> => {
+	// discriminator, price, len, next and 1024 points
+	const pricePath = await createPythAccount(oracleProgram, 8 + 48 + 1024 * 24);
+	await oracleProgram.rpc.initializePricePath({
+		accounts: { pricePath: pricePath.publicKey, price: priceFeed },
+	});
+	for (let i = 0; i < points.length; i += 32) {
+		await oracleProgram.rpc.appendPricePoints(points.slice(i, i + 32), {
+			accounts: { pricePath: pricePath.publicKey },
+		});
+	}
+	return pricePath.publicKey;
+};
+export const advancePricePath = async (
+	oracleProgram: Program,
+	pricePath: PublicKey,
+	priceFeed: PublicKey
+) => {
+	await oracleProgram.rpc.advancePricePath({
+		accounts: { pricePath, price: priceFeed },
+	});
+};
+export const getMappingData = async (
+	oracleProgram: Program,
+	mapping: PublicKey
+) => {
+	const info = await oracleProgram.provider.connection.getAccountInfo(mapping);
+	return parseMappingData(info.data);
+};
+export const getProductData = async (
+	oracleProgram: Program,
+	product: PublicKey
+) => {
+	const info = await oracleProgram.provider.connection.getAccountInfo(product);
+	return parseProductData(info.data);
+};
 export const getFeedData = async (
 	oracleProgram: Program,
 	priceFeed: PublicKey
@@ -466,7 +638,39 @@ const parsePriceData = (data) => {
 		{ priceComponents }
 	);
 };
-const _parseProductData = (data) => {
+const parseMappingData = (data) => {
+	// Pyth magic number.
+	const magic = data.readUInt32LE(0);
+	// Program version.
+	const version = data.readUInt32LE(4);
+	// Account type.
+	const type = data.readUInt32LE(8);
+	// Account used size.
+	const size = data.readUInt32LE(12);
+	// Number of product accounts.
+	const productCount = data.readUInt32LE(16);
+	// Next mapping account in list.
+	const nextMappingAccount = PKorNull(data.slice(24, 56));
+	// Product accounts.
+	const productAccountKeys = [];
+	let offset = 56;
+	for (let i = 0; i < productCount; i++) {
+		productAccountKeys.push(
+			new anchor.web3.PublicKey(data.slice(offset, offset + 32))
+		);
+		offset += 32;
+	}
+	return {
+		magic,
+		version,
+		type,
+		size,
+		productCount,
+		nextMappingAccount,
+		productAccountKeys,
+	};
+};
+const parseProductData = (data) => {
 	// Pyth magic number.
 	const magic = data.readUInt32LE(0);
 	// Program version.
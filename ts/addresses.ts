@@ -2,28 +2,39 @@ import { PublicKey } from '@solana/web3.js';
 import * as anchor from '@project-serum/anchor';
 
 export async function getConfigPublicKeyAndConfig(
-	programId: PublicKey
+	programId: PublicKey,
+	owner: PublicKey,
+	subAccountId: number
 ): Promise<[PublicKey, number]> {
 	return await PublicKey.findProgramAddress(
-		[Buffer.from(anchor.utils.bytes.utf8.encode('drift_client'))],
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('drift_client')),
+			owner.toBuffer(),
+			new anchor.BN(subAccountId).toArrayLike(Buffer, 'le', 2),
+		],
 		programId
 	);
 }
 
 export async function getCollateralVaultPublicKeyAndConfig(
-	programId: PublicKey
+	programId: PublicKey,
+	configPublicKey: PublicKey
 ): Promise<[PublicKey, number]> {
 	return await PublicKey.findProgramAddress(
-		[Buffer.from(anchor.utils.bytes.utf8.encode('collateral_vault'))],
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('collateral_vault')),
+			configPublicKey.toBuffer(),
+		],
 		programId
 	);
 }
 
 export async function getCollateralVaultAuthorityPublicKeyAndConfig(
-	programId: PublicKey
+	programId: PublicKey,
+	configPublicKey: PublicKey
 ): Promise<[PublicKey, number]> {
 	const collateralVaultPublicKey = (
-		await getCollateralVaultPublicKeyAndConfig(programId)
+		await getCollateralVaultPublicKeyAndConfig(programId, configPublicKey)
 	)[0];
 	return await PublicKey.findProgramAddress(
 		[collateralVaultPublicKey.toBuffer()],
@@ -33,10 +44,95 @@ export async function getCollateralVaultAuthorityPublicKeyAndConfig(
 
 export async function getClearingHouseAuthorityPublicKeyAndConfig(
 	programId: PublicKey,
-	clearingHouseProgramId: PublicKey
+	clearingHouseProgramId: PublicKey,
+	configPublicKey: PublicKey
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[clearingHouseProgramId.toBuffer(), configPublicKey.toBuffer()],
+		programId
+	);
+}
+
+export async function getVaultDepositorPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey,
+	depositor: PublicKey
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('vault_depositor')),
+			configPublicKey.toBuffer(),
+			depositor.toBuffer(),
+		],
+		programId
+	);
+}
+
+export async function getRiskLimitsPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('risk_limits')),
+			configPublicKey.toBuffer(),
+		],
+		programId
+	);
+}
+
+export async function getTriggerOrderPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey,
+	marketIndex: anchor.BN
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('trigger_order')),
+			configPublicKey.toBuffer(),
+			marketIndex.toArrayLike(Buffer, 'le', 8),
+		],
+		programId
+	);
+}
+
+export async function getOrderBookPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('order_book')),
+			configPublicKey.toBuffer(),
+		],
+		programId
+	);
+}
+
+export async function getTwapOrderPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey,
+	marketIndex: anchor.BN
+): Promise<[PublicKey, number]> {
+	return await PublicKey.findProgramAddress(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('twap_order')),
+			configPublicKey.toBuffer(),
+			marketIndex.toArrayLike(Buffer, 'le', 8),
+		],
+		programId
+	);
+}
+
+export async function getWithdrawalRequestPublicKeyAndNonce(
+	programId: PublicKey,
+	configPublicKey: PublicKey
 ): Promise<[PublicKey, number]> {
 	return await PublicKey.findProgramAddress(
-		[clearingHouseProgramId.toBuffer()],
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('withdrawal_request')),
+			configPublicKey.toBuffer(),
+		],
 		programId
 	);
 }
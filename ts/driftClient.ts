@@ -7,6 +7,7 @@ import {
 	PositionDirection,
 	UserAccount,
 	UserPositionsAccount,
+	ZERO,
 } from '../deps/protocol-v1/sdk';
 import {
 	Keypair,
@@ -20,21 +21,132 @@ import {
 	getCollateralVaultAuthorityPublicKeyAndConfig,
 	getCollateralVaultPublicKeyAndConfig,
 	getConfigPublicKeyAndConfig,
+	getOrderBookPublicKeyAndNonce,
+	getRiskLimitsPublicKeyAndNonce,
+	getTriggerOrderPublicKeyAndNonce,
+	getTwapOrderPublicKeyAndNonce,
+	getVaultDepositorPublicKeyAndNonce,
+	getWithdrawalRequestPublicKeyAndNonce,
 } from './addresses';
 
 export type Config = {
 	admin: PublicKey;
+	pendingAdmin: PublicKey;
+	owner: PublicKey;
+	subAccountId: number;
+	delegate: PublicKey;
 	collateralVault: PublicKey;
 	authority: PublicKey;
+	clearingHouseUser: PublicKey;
+	clearingHouseUserPositions: PublicKey;
+	totalShares: BN;
+	riskLimits: PublicKey;
+	oracleGuards: OracleGuards;
+	withdrawalDelay: BN;
+	withdrawalDestinations: PublicKey[];
+	pendingWithdrawalDestination: PublicKey;
+	pendingWithdrawalDestinationTs: BN;
+	adminShares: BN;
+};
+
+export type OracleGuards = {
+	maxStalenessSlots: BN;
+	maxConfRatio: BN;
+	maxMarkOracleDivergence: BN;
+};
+
+export type MarketRiskLimit = {
+	enabled: boolean;
+	marketIndex: BN;
+	maxNotional: BN;
+};
+
+export type RiskLimits = {
+	config: PublicKey;
+	maxLeverage: BN;
+	maxOracleSlippage: BN;
+	markets: MarketRiskLimit[];
+};
+
+export type VaultDepositor = {
+	config: PublicKey;
+	depositor: PublicKey;
+	shares: BN;
+};
+
+export type TriggerOrder = {
+	config: PublicKey;
+	marketIndex: BN;
+	direction: PositionDirection;
+	stopLossPrice: BN;
+	takeProfitPrice: BN;
+	keeperFee: BN;
+	pendingKeeperFee: BN;
+	pendingKeeperFeeTs: BN;
+};
+
+export type Order = {
+	id: BN;
+	direction: PositionDirection;
+	quoteAssetAmount: BN;
+	marketIndex: BN;
+	price: BN;
+	expiryTs: BN;
+};
+
+export type OrderBook = {
+	config: PublicKey;
+	nextOrderId: BN;
+	orders: Order[];
+};
+
+export type TwapFill = {
+	ts: BN;
+	quoteAssetAmount: BN;
+	baseAssetAmount: BN;
+};
+
+export type TwapOrder = {
+	config: PublicKey;
+	marketIndex: BN;
+	direction: PositionDirection;
+	totalQuoteAssetAmount: BN;
+	numSlices: BN;
+	sliceInterval: BN;
+	startTs: BN;
+	slicesFilled: BN;
+	filledQuoteAssetAmount: BN;
+	fills: TwapFill[];
+};
+
+export type RebalanceLeg = {
+	marketIndex: BN;
+	direction: PositionDirection;
+	quoteAssetAmount: BN;
+};
+
+export type WithdrawalRequest = {
+	config: PublicKey;
+	amount: BN;
+	requestTs: BN;
 };
 
 export class DriftClient {
 	program: Program;
 	clearingHouse: ClearingHouse;
+	owner: PublicKey;
+	subAccountId: number;
 
-	public constructor(program: Program, clearingHouse: ClearingHouse) {
+	public constructor(
+		program: Program,
+		clearingHouse: ClearingHouse,
+		owner?: PublicKey,
+		subAccountId = 0
+	) {
 		this.program = program;
 		this.clearingHouse = clearingHouse;
+		this.owner = owner ?? program.provider.wallet.publicKey;
+		this.subAccountId = subAccountId;
 		if (!this.clearingHouse.isSubscribed) {
 			throw new Error('ClearingHouse must be subscribed');
 		}
@@ -47,19 +159,29 @@ export class DriftClient {
 	}
 
 	public async getConfigPublicKey(): Promise<PublicKey> {
-		return (await getConfigPublicKeyAndConfig(this.program.programId))[0];
+		return (
+			await getConfigPublicKeyAndConfig(
+				this.program.programId,
+				this.owner,
+				this.subAccountId
+			)
+		)[0];
 	}
 
 	public async getCollateralVaultPublicKey(): Promise<PublicKey> {
 		return (
-			await getCollateralVaultPublicKeyAndConfig(this.program.programId)
+			await getCollateralVaultPublicKeyAndConfig(
+				this.program.programId,
+				await this.getConfigPublicKey()
+			)
 		)[0];
 	}
 
 	public async getCollateralVaultAuthorityPublicKey(): Promise<PublicKey> {
 		return (
 			await getCollateralVaultAuthorityPublicKeyAndConfig(
-				this.program.programId
+				this.program.programId,
+				await this.getConfigPublicKey()
 			)
 		)[0];
 	}
@@ -68,7 +190,8 @@ export class DriftClient {
 		return (
 			await getClearingHouseAuthorityPublicKeyAndConfig(
 				this.program.programId,
-				this.clearingHouse.program.programId
+				this.clearingHouse.program.programId,
+				await this.getConfigPublicKey()
 			)
 		)[0];
 	}
@@ -93,23 +216,201 @@ export class DriftClient {
 		);
 	}
 
-	public async initialize(): Promise<TransactionSignature> {
+	public async getRiskLimitsPublicKey(): Promise<PublicKey> {
+		return (
+			await getRiskLimitsPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey()
+			)
+		)[0];
+	}
+
+	public async getRiskLimits(): Promise<RiskLimits> {
+		return await this.program.account.riskLimits.fetch(
+			await this.getRiskLimitsPublicKey()
+		);
+	}
+
+	public async getVaultDepositorPublicKey(
+		depositor: PublicKey = this.program.provider.wallet.publicKey
+	): Promise<PublicKey> {
+		return (
+			await getVaultDepositorPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey(),
+				depositor
+			)
+		)[0];
+	}
+
+	public async getVaultDepositor(
+		depositor: PublicKey = this.program.provider.wallet.publicKey
+	): Promise<VaultDepositor> {
+		return await this.program.account.vaultDepositor.fetch(
+			await this.getVaultDepositorPublicKey(depositor)
+		);
+	}
+
+	public async getTriggerOrderPublicKey(marketIndex: BN): Promise<PublicKey> {
+		return (
+			await getTriggerOrderPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey(),
+				marketIndex
+			)
+		)[0];
+	}
+
+	public async getTriggerOrder(marketIndex: BN): Promise<TriggerOrder> {
+		return await this.program.account.triggerOrder.fetch(
+			await this.getTriggerOrderPublicKey(marketIndex)
+		);
+	}
+
+	public async getOrderBookPublicKey(): Promise<PublicKey> {
+		return (
+			await getOrderBookPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey()
+			)
+		)[0];
+	}
+
+	public async getOrderBook(): Promise<OrderBook> {
+		return await this.program.account.orderBook.fetch(
+			await this.getOrderBookPublicKey()
+		);
+	}
+
+	public async getTwapOrderPublicKey(marketIndex: BN): Promise<PublicKey> {
+		return (
+			await getTwapOrderPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey(),
+				marketIndex
+			)
+		)[0];
+	}
+
+	public async getTwapOrder(marketIndex: BN): Promise<TwapOrder> {
+		return await this.program.account.twapOrder.fetch(
+			await this.getTwapOrderPublicKey(marketIndex)
+		);
+	}
+
+	public async getWithdrawalRequestPublicKey(): Promise<PublicKey> {
+		return (
+			await getWithdrawalRequestPublicKeyAndNonce(
+				this.program.programId,
+				await this.getConfigPublicKey()
+			)
+		)[0];
+	}
+
+	public async getWithdrawalRequest(): Promise<WithdrawalRequest> {
+		return await this.program.account.withdrawalRequest.fetch(
+			await this.getWithdrawalRequestPublicKey()
+		);
+	}
+
+	private async getClearingHouseTradeAccounts() {
+		const clearingHouseState = this.clearingHouse.getStateAccount();
+		const userAccount = await this.getUserAccount();
+		return {
+			authority: await this.getAuthorityPublicKey(),
+			state: await this.clearingHouse.getStatePublicKey(),
+			user: await this.getClearingHouseUserAccountPublicKey(),
+			userPositions: userAccount.positions,
+			markets: clearingHouseState.markets,
+			fundingPaymentHistory: clearingHouseState.fundingPaymentHistory,
+			fundingRateHistory: clearingHouseState.fundingRateHistory,
+			tradeHistory: clearingHouseState.tradeHistory,
+			program: this.clearingHouse.program.programId,
+		};
+	}
+
+	/**
+	 * Oracle of every open position, in position order. Shares are priced at
+	 * these rather than the mark price. excludedMarketIndex leaves out a
+	 * position the instruction closes first.
+	 */
+	private async getEquityOracleAccounts(excludedMarketIndex?: BN) {
+		const userPositionsAccount = await this.getUserPositionsAccount();
+		return userPositionsAccount.positions
+			.filter((position) => !position.baseAssetAmount.eq(ZERO))
+			.filter(
+				(position) =>
+					excludedMarketIndex === undefined ||
+					!position.marketIndex.eq(excludedMarketIndex)
+			)
+			.map((position) => {
+				return {
+					pubkey: this.clearingHouse.getMarket(position.marketIndex).amm
+						.oracle,
+					isWritable: false,
+					isSigner: false,
+				};
+			});
+	}
+
+	private async getClearingHouseCollateralAccounts() {
+		const clearingHouseState = this.clearingHouse.getStateAccount();
+		const userAccount = await this.getUserAccount();
+		return {
+			authority: await this.getAuthorityPublicKey(),
+			state: await this.clearingHouse.getStatePublicKey(),
+			user: await this.getClearingHouseUserAccountPublicKey(),
+			userPositions: userAccount.positions,
+			markets: clearingHouseState.markets,
+			collateralVault: clearingHouseState.collateralVault,
+			collateralVaultAuthority: clearingHouseState.collateralVaultAuthority,
+			insuranceVault: clearingHouseState.insuranceVault,
+			insuranceVaultAuthority: clearingHouseState.insuranceVaultAuthority,
+			fundingPaymentHistory: clearingHouseState.fundingPaymentHistory,
+			depositHistory: clearingHouseState.depositHistory,
+			tokenProgram: TOKEN_PROGRAM_ID,
+			program: this.clearingHouse.program.programId,
+		};
+	}
+
+	/**
+	 * withdrawalDestination seeds the withdrawal destinations, the default public
+	 * key leaves them empty so nothing can be withdrawn until one is accepted
+	 */
+	public async initialize(
+		withdrawalDestination: PublicKey
+	): Promise<TransactionSignature> {
 		const [configPublicKey, configNonce] = await getConfigPublicKeyAndConfig(
-			this.program.programId
+			this.program.programId,
+			this.owner,
+			this.subAccountId
 		);
 
 		const [collateralVaultPublicKey, collateralVaultNonce] =
-			await getCollateralVaultPublicKeyAndConfig(this.program.programId);
+			await getCollateralVaultPublicKeyAndConfig(
+				this.program.programId,
+				configPublicKey
+			);
+
+		const [riskLimitsPublicKey, riskLimitsNonce] =
+			await getRiskLimitsPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey
+			);
 
 		const authority = await this.getAuthorityPublicKey();
 
 		return await this.program.rpc.initialize(
 			configNonce,
 			collateralVaultNonce,
+			this.subAccountId,
+			riskLimitsNonce,
+			withdrawalDestination,
 			{
 				accounts: {
 					admin: this.program.provider.wallet.publicKey,
 					config: configPublicKey,
+					riskLimits: riskLimitsPublicKey,
 					authority,
 					collateralMint: this.clearingHouse.getStateAccount().collateralMint,
 					collateralVault: collateralVaultPublicKey,
@@ -150,26 +451,16 @@ export class DriftClient {
 		amount: BN,
 		collateralAccount: PublicKey
 	): Promise<TransactionSignature> {
-		const clearingHouseState = this.clearingHouse.getStateAccount();
-		const userAccount = await this.getUserAccount();
 		return await this.program.rpc.depositCollateral(amount, {
 			accounts: {
 				admin: this.program.provider.wallet.publicKey,
 				adminCollateralAccount: collateralAccount,
 				config: await this.getConfigPublicKey(),
-				clearingHouseState: await this.clearingHouse.getStatePublicKey(),
-				clearingHouseUser: await this.getClearingHouseUserAccountPublicKey(),
-				clearingHouseUserPositions: userAccount.positions,
-				clearingHouseMarkets: clearingHouseState.markets,
-				clearingHouseCollateralVault: clearingHouseState.collateralVault,
-				clearingHouseDepositHistory: clearingHouseState.depositHistory,
-				clearingHouseFundingPaymentHistory:
-					clearingHouseState.fundingPaymentHistory,
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
 				collateralVault: await this.getCollateralVaultPublicKey(),
-				authority: await this.getAuthorityPublicKey(),
-				tokenProgram: TOKEN_PROGRAM_ID,
-				clearingHouseProgram: this.clearingHouse.program.programId,
 			},
+			remainingAccounts: await this.getEquityOracleAccounts(),
 		});
 	}
 
@@ -177,34 +468,68 @@ export class DriftClient {
 		amount: BN,
 		collateralAccount: PublicKey
 	): Promise<TransactionSignature> {
-		const clearingHouseState = this.clearingHouse.getStateAccount();
-		const userAccount = await this.getUserAccount();
 		return await this.program.rpc.withdrawCollateral(amount, {
 			accounts: {
 				admin: this.program.provider.wallet.publicKey,
 				adminCollateralAccount: collateralAccount,
 				config: await this.getConfigPublicKey(),
-				clearingHouseState: await this.clearingHouse.getStatePublicKey(),
-				clearingHouseUser: await this.getClearingHouseUserAccountPublicKey(),
-				clearingHouseUserPositions: userAccount.positions,
-				clearingHouseMarkets: clearingHouseState.markets,
-				clearingHouseCollateralVault: clearingHouseState.collateralVault,
-				clearingHouseCollateralVaultAuthority:
-					clearingHouseState.collateralVaultAuthority,
-				clearingHouseInsuranceVault: clearingHouseState.insuranceVault,
-				clearingHouseInsuranceVaultAuthority:
-					clearingHouseState.insuranceVaultAuthority,
-				clearingHouseDepositHistory: clearingHouseState.depositHistory,
-				clearingHouseFundingPaymentHistory:
-					clearingHouseState.fundingPaymentHistory,
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
 				collateralVault: await this.getCollateralVaultPublicKey(),
-				authority: await this.getAuthorityPublicKey(),
-				tokenProgram: TOKEN_PROGRAM_ID,
-				clearingHouseProgram: this.clearingHouse.program.programId,
+			},
+			remainingAccounts: await this.getEquityOracleAccounts(),
+		});
+	}
+
+	public async requestWithdrawal(amount: BN): Promise<TransactionSignature> {
+		const configPublicKey = await this.getConfigPublicKey();
+		const [withdrawalRequestPublicKey, withdrawalRequestNonce] =
+			await getWithdrawalRequestPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey
+			);
+		return await this.program.rpc.requestWithdrawal(
+			withdrawalRequestNonce,
+			amount,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: configPublicKey,
+					withdrawalRequest: withdrawalRequestPublicKey,
+					rent: SYSVAR_RENT_PUBKEY,
+					systemProgram: anchor.web3.SystemProgram.programId,
+				},
+			}
+		);
+	}
+
+	public async cancelWithdrawal(): Promise<TransactionSignature> {
+		return await this.program.rpc.cancelWithdrawal({
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				withdrawalRequest: await this.getWithdrawalRequestPublicKey(),
 			},
 		});
 	}
 
+	public async claimWithdrawal(
+		collateralAccount: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.claimWithdrawal({
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				adminCollateralAccount: collateralAccount,
+				config: await this.getConfigPublicKey(),
+				withdrawalRequest: await this.getWithdrawalRequestPublicKey(),
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
+				collateralVault: await this.getCollateralVaultPublicKey(),
+			},
+			remainingAccounts: await this.getEquityOracleAccounts(),
+		});
+	}
+
 	public async openPosition(
 		direction: PositionDirection,
 		amount: BN,
@@ -241,8 +566,6 @@ export class DriftClient {
 
 		const priceOracle = this.clearingHouse.getMarket(marketIndex).amm.oracle;
 
-		const clearingHouseState = this.clearingHouse.getStateAccount();
-		const userAccount = await this.getUserAccount();
 		return await this.program.rpc.openPosition(
 			direction,
 			amount,
@@ -251,20 +574,65 @@ export class DriftClient {
 			optionalAccounts,
 			{
 				accounts: {
-					admin: this.program.provider.wallet.publicKey,
+					trader: this.program.provider.wallet.publicKey,
 					config: await this.getConfigPublicKey(),
-					clearingHouseState: await this.clearingHouse.getStatePublicKey(),
-					clearingHouseUser: await this.getClearingHouseUserAccountPublicKey(),
-					clearingHouseUserPositions: userAccount.positions,
-					clearingHouseMarkets: clearingHouseState.markets,
+					riskLimits: await this.getRiskLimitsPublicKey(),
+					clearingHouse: await this.getClearingHouseTradeAccounts(),
+					oracle: priceOracle,
+				},
+			}
+		);
+	}
+
+	public async reducePosition(
+		direction: PositionDirection,
+		amount: BN,
+		marketIndex: BN,
+		limitPrice?: BN,
+		discountToken?: PublicKey,
+		referrer?: PublicKey
+	): Promise<TransactionSignature> {
+		if (limitPrice == undefined) {
+			limitPrice = new BN(0); // no limit
+		}
+
+		const optionalAccounts = {
+			discountToken: false,
+			referrer: false,
+		};
+		const remainingAccounts = [];
+		if (discountToken) {
+			optionalAccounts.discountToken = true;
+			remainingAccounts.push({
+				pubkey: discountToken,
+				isWritable: false,
+				isSigner: false,
+			});
+		}
+		if (referrer) {
+			optionalAccounts.referrer = true;
+			remainingAccounts.push({
+				pubkey: referrer,
+				isWritable: true,
+				isSigner: false,
+			});
+		}
+
+		const priceOracle = this.clearingHouse.getMarket(marketIndex).amm.oracle;
+
+		return await this.program.rpc.reducePosition(
+			direction,
+			amount,
+			marketIndex,
+			limitPrice,
+			optionalAccounts,
+			{
+				accounts: {
+					trader: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					riskLimits: await this.getRiskLimitsPublicKey(),
+					clearingHouse: await this.getClearingHouseTradeAccounts(),
 					oracle: priceOracle,
-					clearingHouseTradeHistory: clearingHouseState.tradeHistory,
-					clearingHouseFundingPaymentHistory:
-						clearingHouseState.fundingPaymentHistory,
-					clearingHouseFundingRateHistory:
-						clearingHouseState.fundingRateHistory,
-					authority: await this.getAuthorityPublicKey(),
-					clearingHouseProgram: this.clearingHouse.program.programId,
 				},
 			}
 		);
@@ -299,24 +667,469 @@ export class DriftClient {
 
 		const priceOracle = this.clearingHouse.getMarket(marketIndex).amm.oracle;
 
-		const clearingHouseState = this.clearingHouse.getStateAccount();
-		const userAccount = await this.getUserAccount();
 		return await this.program.rpc.closePosition(marketIndex, optionalAccounts, {
 			accounts: {
-				admin: this.program.provider.wallet.publicKey,
+				trader: this.program.provider.wallet.publicKey,
 				config: await this.getConfigPublicKey(),
-				clearingHouseState: await this.clearingHouse.getStatePublicKey(),
-				clearingHouseUser: await this.getClearingHouseUserAccountPublicKey(),
-				clearingHouseUserPositions: userAccount.positions,
-				clearingHouseMarkets: clearingHouseState.markets,
+				clearingHouse: await this.getClearingHouseTradeAccounts(),
 				oracle: priceOracle,
-				clearingHouseTradeHistory: clearingHouseState.tradeHistory,
-				clearingHouseFundingPaymentHistory:
-					clearingHouseState.fundingPaymentHistory,
-				clearingHouseFundingRateHistory: clearingHouseState.fundingRateHistory,
-				authority: await this.getAuthorityPublicKey(),
-				clearingHouseProgram: this.clearingHouse.program.programId,
 			},
 		});
 	}
+
+	/**
+	 * Trades toward targetBaseAssetAmount, opening, reducing, closing or flipping the position.
+	 * maxSlippage bounds the trade price around the mark price
+	 */
+	public async setTargetPosition(
+		marketIndex: BN,
+		targetBaseAssetAmount: BN,
+		maxSlippage: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.setTargetPosition(
+			marketIndex,
+			targetBaseAssetAmount,
+			maxSlippage,
+			{
+				accounts: {
+					trader: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					riskLimits: await this.getRiskLimitsPublicKey(),
+					clearingHouse: await this.getClearingHouseTradeAccounts(),
+					oracle: this.clearingHouse.getMarket(marketIndex).amm.oracle,
+				},
+			}
+		);
+	}
+
+	/**
+	 * Opens every leg in one transaction, so either all legs apply or none do
+	 */
+	public async rebalance(legs: RebalanceLeg[]): Promise<TransactionSignature> {
+		const remainingAccounts = legs.map((leg) => {
+			return {
+				pubkey: this.clearingHouse.getMarket(leg.marketIndex).amm.oracle,
+				isWritable: false,
+				isSigner: false,
+			};
+		});
+
+		return await this.program.rpc.rebalance(legs, {
+			accounts: {
+				trader: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				riskLimits: await this.getRiskLimitsPublicKey(),
+				clearingHouse: await this.getClearingHouseTradeAccounts(),
+			},
+			remainingAccounts,
+		});
+	}
+
+	public async updateDelegate(
+		delegate: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateDelegate(delegate, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async proposeAdmin(
+		pendingAdmin: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.proposeAdmin(pendingAdmin, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async acceptAdmin(): Promise<TransactionSignature> {
+		return await this.program.rpc.acceptAdmin({
+			accounts: {
+				pendingAdmin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async updateRiskLimits(
+		maxLeverage: BN,
+		maxOracleSlippage: BN,
+		markets: MarketRiskLimit[]
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateRiskLimits(
+			maxLeverage,
+			maxOracleSlippage,
+			padMarketRiskLimits(markets),
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					riskLimits: await this.getRiskLimitsPublicKey(),
+				},
+			}
+		);
+	}
+
+	public async updateOracleGuards(
+		oracleGuards: OracleGuards
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateOracleGuards(oracleGuards, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async updateWithdrawalDelay(
+		withdrawalDelay: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateWithdrawalDelay(withdrawalDelay, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	/**
+	 * The destination can be accepted once the withdrawal delay, and at least a
+	 * day, has passed
+	 */
+	public async proposeWithdrawalDestination(
+		destination: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.proposeWithdrawalDestination(destination, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async acceptWithdrawalDestination(): Promise<TransactionSignature> {
+		return await this.program.rpc.acceptWithdrawalDestination({
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async removeWithdrawalDestination(
+		destination: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.removeWithdrawalDestination(destination, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+			},
+		});
+	}
+
+	public async initializeVaultDepositor(): Promise<TransactionSignature> {
+		const depositor = this.program.provider.wallet.publicKey;
+		const configPublicKey = await this.getConfigPublicKey();
+		const [vaultDepositorPublicKey, vaultDepositorNonce] =
+			await getVaultDepositorPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey,
+				depositor
+			);
+		return await this.program.rpc.initializeVaultDepositor(
+			vaultDepositorNonce,
+			{
+				accounts: {
+					depositor,
+					config: configPublicKey,
+					vaultDepositor: vaultDepositorPublicKey,
+					rent: SYSVAR_RENT_PUBKEY,
+					systemProgram: anchor.web3.SystemProgram.programId,
+				},
+			}
+		);
+	}
+
+	public async vaultDeposit(
+		amount: BN,
+		collateralAccount: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.vaultDeposit(amount, {
+			accounts: {
+				depositor: this.program.provider.wallet.publicKey,
+				depositorCollateralAccount: collateralAccount,
+				vaultDepositor: await this.getVaultDepositorPublicKey(),
+				config: await this.getConfigPublicKey(),
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
+				collateralVault: await this.getCollateralVaultPublicKey(),
+			},
+			remainingAccounts: await this.getEquityOracleAccounts(),
+		});
+	}
+
+	public async vaultWithdraw(
+		shares: BN,
+		collateralAccount: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.vaultWithdraw(shares, {
+			accounts: {
+				depositor: this.program.provider.wallet.publicKey,
+				depositorCollateralAccount: collateralAccount,
+				vaultDepositor: await this.getVaultDepositorPublicKey(),
+				config: await this.getConfigPublicKey(),
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
+				collateralVault: await this.getCollateralVaultPublicKey(),
+			},
+			remainingAccounts: await this.getEquityOracleAccounts(),
+		});
+	}
+
+	public async initializeTriggerOrder(
+		marketIndex: BN,
+		stopLossPrice: BN,
+		takeProfitPrice: BN,
+		keeperFee: BN
+	): Promise<TransactionSignature> {
+		const configPublicKey = await this.getConfigPublicKey();
+		const [triggerOrderPublicKey, triggerOrderNonce] =
+			await getTriggerOrderPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey,
+				marketIndex
+			);
+		return await this.program.rpc.initializeTriggerOrder(
+			triggerOrderNonce,
+			marketIndex,
+			stopLossPrice,
+			takeProfitPrice,
+			keeperFee,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: configPublicKey,
+					triggerOrder: triggerOrderPublicKey,
+					clearingHouseUserPositions: (await this.getUserAccount()).positions,
+					rent: SYSVAR_RENT_PUBKEY,
+					systemProgram: anchor.web3.SystemProgram.programId,
+				},
+			}
+		);
+	}
+
+	public async updateTriggerOrder(
+		marketIndex: BN,
+		stopLossPrice: BN,
+		takeProfitPrice: BN,
+		keeperFee: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.updateTriggerOrder(
+			stopLossPrice,
+			takeProfitPrice,
+			keeperFee,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					triggerOrder: await this.getTriggerOrderPublicKey(marketIndex),
+					clearingHouseUserPositions: (await this.getUserAccount()).positions,
+				},
+			}
+		);
+	}
+
+	/**
+	 * Can be sent by any keeper, who is paid the trigger's keeper fee into
+	 * collateralAccount. The fee is burned from the admin shares.
+	 */
+	public async executeTrigger(
+		marketIndex: BN,
+		collateralAccount: PublicKey
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.executeTrigger({
+			accounts: {
+				keeper: this.program.provider.wallet.publicKey,
+				keeperCollateralAccount: collateralAccount,
+				config: await this.getConfigPublicKey(),
+				triggerOrder: await this.getTriggerOrderPublicKey(marketIndex),
+				collateralVault: await this.getCollateralVaultPublicKey(),
+				clearingHouse: await this.getClearingHouseTradeAccounts(),
+				clearingHouseCollateral:
+					await this.getClearingHouseCollateralAccounts(),
+				oracle: this.clearingHouse.getMarket(marketIndex).amm.oracle,
+			},
+			remainingAccounts: await this.getEquityOracleAccounts(marketIndex),
+		});
+	}
+
+	public async initializeOrderBook(): Promise<TransactionSignature> {
+		const configPublicKey = await this.getConfigPublicKey();
+		const [orderBookPublicKey, orderBookNonce] =
+			await getOrderBookPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey
+			);
+		return await this.program.rpc.initializeOrderBook(orderBookNonce, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: configPublicKey,
+				orderBook: orderBookPublicKey,
+				rent: SYSVAR_RENT_PUBKEY,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
+	public async placeOrder(
+		direction: PositionDirection,
+		amount: BN,
+		marketIndex: BN,
+		price: BN,
+		expiryTs: BN = new BN(0)
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.placeOrder(
+			direction,
+			amount,
+			marketIndex,
+			price,
+			expiryTs,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					orderBook: await this.getOrderBookPublicKey(),
+				},
+			}
+		);
+	}
+
+	public async cancelOrder(orderId: BN): Promise<TransactionSignature> {
+		return await this.program.rpc.cancelOrder(orderId, {
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				orderBook: await this.getOrderBookPublicKey(),
+			},
+		});
+	}
+
+	/**
+	 * Can be sent by any keeper once the mark price crosses the order's price
+	 */
+	public async fillOrder(orderId: BN): Promise<TransactionSignature> {
+		const orderBook = await this.getOrderBook();
+		const order = orderBook.orders.find((order) => order.id.eq(orderId));
+		if (!order) {
+			throw new Error(`Order ${orderId.toString()} not found`);
+		}
+
+		return await this.program.rpc.fillOrder(orderId, {
+			accounts: {
+				keeper: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				riskLimits: await this.getRiskLimitsPublicKey(),
+				orderBook: await this.getOrderBookPublicKey(),
+				clearingHouse: await this.getClearingHouseTradeAccounts(),
+				oracle: this.clearingHouse.getMarket(order.marketIndex).amm.oracle,
+			},
+		});
+	}
+
+	public async initializeTwapOrder(
+		marketIndex: BN
+	): Promise<TransactionSignature> {
+		const configPublicKey = await this.getConfigPublicKey();
+		const [twapOrderPublicKey, twapOrderNonce] =
+			await getTwapOrderPublicKeyAndNonce(
+				this.program.programId,
+				configPublicKey,
+				marketIndex
+			);
+		return await this.program.rpc.initializeTwapOrder(
+			twapOrderNonce,
+			marketIndex,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: configPublicKey,
+					twapOrder: twapOrderPublicKey,
+					rent: SYSVAR_RENT_PUBKEY,
+					systemProgram: anchor.web3.SystemProgram.programId,
+				},
+			}
+		);
+	}
+
+	public async startTwap(
+		marketIndex: BN,
+		direction: PositionDirection,
+		totalAmount: BN,
+		numSlices: BN,
+		duration: BN
+	): Promise<TransactionSignature> {
+		return await this.program.rpc.startTwap(
+			direction,
+			totalAmount,
+			numSlices,
+			duration,
+			{
+				accounts: {
+					admin: this.program.provider.wallet.publicKey,
+					config: await this.getConfigPublicKey(),
+					twapOrder: await this.getTwapOrderPublicKey(marketIndex),
+				},
+			}
+		);
+	}
+
+	public async cancelTwap(marketIndex: BN): Promise<TransactionSignature> {
+		return await this.program.rpc.cancelTwap({
+			accounts: {
+				admin: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				twapOrder: await this.getTwapOrderPublicKey(marketIndex),
+			},
+		});
+	}
+
+	/**
+	 * Can be sent by any keeper once the next slice is due
+	 */
+	public async crankTwap(marketIndex: BN): Promise<TransactionSignature> {
+		return await this.program.rpc.crankTwap({
+			accounts: {
+				keeper: this.program.provider.wallet.publicKey,
+				config: await this.getConfigPublicKey(),
+				riskLimits: await this.getRiskLimitsPublicKey(),
+				twapOrder: await this.getTwapOrderPublicKey(marketIndex),
+				clearingHouse: await this.getClearingHouseTradeAccounts(),
+				oracle: this.clearingHouse.getMarket(marketIndex).amm.oracle,
+			},
+		});
+	}
+}
+
+const MAX_RISK_LIMIT_MARKETS = 8;
+
+function padMarketRiskLimits(markets: MarketRiskLimit[]): MarketRiskLimit[] {
+	if (markets.length > MAX_RISK_LIMIT_MARKETS) {
+		throw new Error(
+			`At most ${MAX_RISK_LIMIT_MARKETS} markets can have risk limits`
+		);
+	}
+	const padded = [...markets];
+	while (padded.length < MAX_RISK_LIMIT_MARKETS) {
+		padded.push({
+			enabled: false,
+			marketIndex: new BN(0),
+			maxNotional: new BN(0),
+		});
+	}
+	return padded;
 }